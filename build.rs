@@ -1,11 +1,22 @@
 extern crate bindgen;
 
+use std::fs::File;
+use std::io::Write;
+
+/// The attribute bindgen links libgbm with, and the one written instead so
+/// that the checked-in bindings work with and without the dlopen feature.
+const LINK: &'static str = "#[link(name = \"gbm\", kind = \"dylib\")]";
+const LINK_UNLESS_DLOPEN: &'static str = "#[cfg_attr(not(feature = \"dlopen\"), link(name = \"gbm\", kind = \"dylib\"))]";
+
 fn generate_shim_bindings() {
     let mut builder = bindgen::Builder::new("src/ffi/cc/gbm_shim.c");
     builder.link("gbm", bindgen::LinkType::Dynamic);
     builder.convert_macros(true);
     match builder.generate() {
-        Ok(b) => b.write_to_file("src/ffi/gbm_shim.rs").unwrap(),
+        Ok(b) => {
+            let code = b.to_string().replace(LINK, LINK_UNLESS_DLOPEN);
+            File::create("src/ffi/gbm_shim.rs").unwrap().write_all(code.as_bytes()).unwrap();
+        },
         Err(e) => panic!(e)
     };
 }
@@ -0,0 +1,89 @@
+use std::fs::File;
+use std::os::unix::io::{BorrowedFd, OwnedFd};
+
+use super::{Device, Buffer, BufferFlags, DmabufDescriptor, Format, TRANSFER_READ_WRITE};
+use error::Result;
+
+/// The properties shared by buffers from every `Allocator`.
+pub trait BufferObject {
+    /// Returns the width and height of the buffer.
+    fn size(&self) -> (u32, u32);
+
+    /// Returns the stride of the buffer.
+    fn stride(&self) -> u32;
+
+    /// Returns the fourcc format code of the buffer.
+    fn format(&self) -> u32;
+
+    /// Describes the buffer, to be exported along with its file
+    /// descriptors.
+    fn descriptor(&self) -> Result<DmabufDescriptor>;
+}
+
+/// An `Allocator` creates, imports, exports and maps buffers.
+///
+/// Higher-level code such as swapchains, pools and compositors can be
+/// written against this trait and run on libgbm through `Device` or on any
+/// other backend.
+pub trait Allocator {
+    /// The buffers created by this allocator.
+    type Buffer<'a>: BufferObject where Self: 'a;
+
+    /// Creates a buffer using the given size and parameters.
+    fn create_buffer(&self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Self::Buffer<'_>>;
+
+    /// Imports a dma-buf with one file descriptor per plane of the
+    /// descriptor as a buffer.
+    fn import_buffer(&self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Self::Buffer<'_>>;
+
+    /// Exports a buffer as one dma-buf file descriptor per plane of its
+    /// descriptor.
+    fn export_buffer(&self, buffer: &Self::Buffer<'_>) -> Result<Vec<OwnedFd>>;
+
+    /// Maps a buffer for reading and writing, and calls `func` with the
+    /// mapped memory and its stride.
+    fn map_buffer<R, M>(&self, buffer: &mut Self::Buffer<'_>, func: M) -> Result<R>
+        where M: FnOnce(&mut [u8], u32) -> R;
+}
+
+impl<'a, F> BufferObject for Buffer<'a, F> where F: AsRef<File> {
+    fn size(&self) -> (u32, u32) {
+        Buffer::size(self)
+    }
+
+    fn stride(&self) -> u32 {
+        Buffer::stride(self)
+    }
+
+    fn format(&self) -> u32 {
+        Buffer::format(self)
+    }
+
+    fn descriptor(&self) -> Result<DmabufDescriptor> {
+        Buffer::descriptor(self)
+    }
+}
+
+impl<F> Allocator for Device<F> where F: AsRef<File> {
+    type Buffer<'a> = Buffer<'a, F> where Self: 'a;
+
+    fn create_buffer(&self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Buffer<'_, F>> {
+        self.buffer(size, format, flags)
+    }
+
+    fn import_buffer(&self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Buffer<'_, F>> {
+        self.import_dmabuf(descriptor, fds)
+    }
+
+    fn export_buffer(&self, buffer: &Buffer<'_, F>) -> Result<Vec<OwnedFd>> {
+        buffer.plane_fds()
+    }
+
+    fn map_buffer<R, M>(&self, buffer: &mut Buffer<'_, F>, func: M) -> Result<R>
+        where M: FnOnce(&mut [u8], u32) -> R
+    {
+        let mut mapping = try!(buffer.map(TRANSFER_READ_WRITE));
+        let stride = mapping.stride();
+        Ok(func(&mut mapping, stride))
+    }
+}
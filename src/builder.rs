@@ -0,0 +1,335 @@
+//! Builders that create a buffer or surface with the first of several
+//! usages, formats and modifiers the driver accepts, and report which one
+//! it was.
+
+use std::fs::File;
+
+use super::{Buffer, BufferFlags, Device, Format, Modifier, Surface};
+use error::{Result, Error};
+
+/// One of the configurations a builder tries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BuildConfig {
+    /// The format.
+    pub format: Format,
+    /// The modifier of a built buffer, or none if the driver chose the
+    /// layout alone or libgbm cannot report it. For a surface, whose buffers
+    /// do not exist yet, the explicit modifiers libgbm chose from.
+    pub modifiers: Vec<Modifier>,
+    /// The usage flags, including `LINEAR` if libgbm could not take the
+    /// modifiers and a linear layout was used instead.
+    pub usage: BufferFlags,
+    /// The name given to the builder.
+    pub name: Option<String>
+}
+
+/// A buffer or surface created by a builder, with the configuration that
+/// succeeded.
+#[derive(Debug)]
+pub struct Built<T> {
+    /// The buffer or surface.
+    pub value: T,
+    /// The configuration it was created with.
+    pub config: BuildConfig
+}
+
+/// What buffer and surface builders are given.
+#[derive(Debug, Clone)]
+struct Options {
+    size: Option<(u32, u32)>,
+    format: Option<Format>,
+    fallback_formats: Vec<Format>,
+    modifiers: Vec<Modifier>,
+    usage: BufferFlags,
+    fallback_usages: Vec<BufferFlags>,
+    name: Option<String>
+}
+
+impl Options {
+    fn new() -> Options {
+        Options {
+            size: None,
+            format: None,
+            fallback_formats: Vec::new(),
+            modifiers: Vec::new(),
+            usage: BufferFlags::empty(),
+            fallback_usages: Vec::new(),
+            name: None
+        }
+    }
+
+    /// Returns the configurations to try, in order.
+    ///
+    /// Every format is tried with the preferred usage before any fallback
+    /// usage, as where a buffer is placed matters more than its format.
+    /// Each format is tried with the explicit modifiers first, and then
+    /// without any if there were none or `Modifier::INVALID` was among them.
+    fn candidates(&self) -> Result<Vec<BuildConfig>> {
+        let format = try!(self.format.ok_or(Error::MissingParameter("format")));
+        let explicit: Vec<Modifier> = self.modifiers.iter().cloned().filter(|&modifier| modifier != Modifier::INVALID).collect();
+        let implicit = explicit.is_empty() || self.modifiers.contains(&Modifier::INVALID);
+
+        let mut candidates = Vec::new();
+        for &usage in Some(self.usage).iter().chain(&self.fallback_usages) {
+            for &format in Some(format).iter().chain(&self.fallback_formats) {
+                let config = BuildConfig { format: format, modifiers: Vec::new(), usage: usage, name: self.name.clone() };
+                if !explicit.is_empty() {
+                    candidates.push(BuildConfig { modifiers: explicit.clone(), ..config.clone() });
+                }
+                if implicit {
+                    candidates.push(config);
+                }
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Creates a value with each candidate until one succeeds, returning the
+    /// error of the first candidate if none does.
+    fn build<T, C>(&self, mut create: C) -> Result<Built<T>> where C: FnMut((u32, u32), &BuildConfig) -> Result<T> {
+        let size = try!(self.size.ok_or(Error::MissingParameter("size")));
+        let mut first_error = None;
+        for config in try!(self.candidates()) {
+            match create(size, &config) {
+                Ok(value) => return Ok(Built { value: value, config: config }),
+                Err(err) => if first_error.is_none() { first_error = Some(err) }
+            }
+        }
+        // Only a builder without a format has no candidates.
+        Err(first_error.unwrap_or(Error::MissingParameter("format")))
+    }
+}
+
+/// Creates a `Buffer`, trying fallback usages, formats and modifiers in
+/// order.
+///
+/// The size and format must be given before calling `build`.
+pub struct BufferBuilder<'a, F> where F: 'a + AsRef<File> {
+    device: &'a Device<F>,
+    options: Options
+}
+
+impl<'a, F> BufferBuilder<'a, F> where F: AsRef<File> {
+    /// Creates a builder for buffers on a device.
+    pub fn new(device: &'a Device<F>) -> BufferBuilder<'a, F> {
+        BufferBuilder { device: device, options: Options::new() }
+    }
+
+    /// Sets the width and height of the buffer.
+    pub fn size(mut self, size: (u32, u32)) -> BufferBuilder<'a, F> {
+        self.options.size = Some(size);
+        self
+    }
+
+    /// Sets the preferred format.
+    pub fn format(mut self, format: Format) -> BufferBuilder<'a, F> {
+        self.options.format = Some(format);
+        self
+    }
+
+    /// Sets the formats to try, in order, if the preferred one fails.
+    pub fn fallback_formats(mut self, formats: &[Format]) -> BufferBuilder<'a, F> {
+        self.options.fallback_formats = formats.to_vec();
+        self
+    }
+
+    /// Sets the modifiers libgbm may choose from, such as those of a
+    /// `negotiate::Choice`. Including `Modifier::INVALID` allows falling back
+    /// to a layout chosen by the driver.
+    pub fn modifiers(mut self, modifiers: &[Modifier]) -> BufferBuilder<'a, F> {
+        self.options.modifiers = modifiers.to_vec();
+        self
+    }
+
+    /// Sets the usage flags. Defaults to none.
+    pub fn usage(mut self, usage: BufferFlags) -> BufferBuilder<'a, F> {
+        self.options.usage = usage;
+        self
+    }
+
+    /// Sets the usage flags to try, in order, if the preferred ones fail,
+    /// such as `RENDERING` for a buffer preferably placed for
+    /// `SCANOUT | RENDERING`.
+    pub fn fallback_usages(mut self, usages: &[BufferFlags]) -> BufferBuilder<'a, F> {
+        self.options.fallback_usages = usages.to_vec();
+        self
+    }
+
+    /// Names the buffer, to tell it apart in logs. libgbm cannot label
+    /// buffers, so the name is only reported back in the `BuildConfig`.
+    pub fn name(mut self, name: &str) -> BufferBuilder<'a, F> {
+        self.options.name = Some(name.to_owned());
+        self
+    }
+
+    /// Creates the buffer with the first configuration that works, and
+    /// records the modifier and usage the buffer ended up with.
+    ///
+    /// Fails with `Error::MissingParameter` if the size or format was not
+    /// given.
+    pub fn build(self) -> Result<Built<Buffer<'a, F>>> {
+        let device = self.device;
+        let mut built = try!(self.options.build(|size, config| {
+            device.buffer_with_modifiers(size, config.format, &config.modifiers, config.usage)
+        }));
+        built.config.modifiers = match built.value.modifier() {
+            Ok(Modifier::INVALID) | Err(_) => Vec::new(),
+            Ok(modifier) => vec![modifier]
+        };
+        built.config.usage = built.value.usage();
+        Ok(built)
+    }
+}
+
+/// Creates a `Surface`, trying fallback usages, formats and modifiers in
+/// order.
+///
+/// The size and format must be given before calling `build`.
+pub struct SurfaceBuilder<'a, F> where F: 'a + AsRef<File> {
+    device: &'a Device<F>,
+    options: Options
+}
+
+impl<'a, F> SurfaceBuilder<'a, F> where F: AsRef<File> {
+    /// Creates a builder for surfaces on a device.
+    pub fn new(device: &'a Device<F>) -> SurfaceBuilder<'a, F> {
+        SurfaceBuilder { device: device, options: Options::new() }
+    }
+
+    /// Sets the width and height of the surface.
+    pub fn size(mut self, size: (u32, u32)) -> SurfaceBuilder<'a, F> {
+        self.options.size = Some(size);
+        self
+    }
+
+    /// Sets the preferred format.
+    pub fn format(mut self, format: Format) -> SurfaceBuilder<'a, F> {
+        self.options.format = Some(format);
+        self
+    }
+
+    /// Sets the formats to try, in order, if the preferred one fails.
+    pub fn fallback_formats(mut self, formats: &[Format]) -> SurfaceBuilder<'a, F> {
+        self.options.fallback_formats = formats.to_vec();
+        self
+    }
+
+    /// Sets the modifiers libgbm may choose from, see
+    /// `BufferBuilder::modifiers`.
+    pub fn modifiers(mut self, modifiers: &[Modifier]) -> SurfaceBuilder<'a, F> {
+        self.options.modifiers = modifiers.to_vec();
+        self
+    }
+
+    /// Sets the usage flags. Defaults to none.
+    pub fn usage(mut self, usage: BufferFlags) -> SurfaceBuilder<'a, F> {
+        self.options.usage = usage;
+        self
+    }
+
+    /// Sets the usage flags to try, in order, if the preferred ones fail, see
+    /// `BufferBuilder::fallback_usages`.
+    pub fn fallback_usages(mut self, usages: &[BufferFlags]) -> SurfaceBuilder<'a, F> {
+        self.options.fallback_usages = usages.to_vec();
+        self
+    }
+
+    /// Names the surface, see `BufferBuilder::name`.
+    pub fn name(mut self, name: &str) -> SurfaceBuilder<'a, F> {
+        self.options.name = Some(name.to_owned());
+        self
+    }
+
+    /// Creates the surface with the first configuration that works, and
+    /// records the usage the surface ended up with.
+    ///
+    /// Fails with `Error::MissingParameter` if the size or format was not
+    /// given.
+    pub fn build(self) -> Result<Built<Surface<F>>> {
+        let device = self.device;
+        let mut built = try!(self.options.build(|size, config| {
+            Surface::with_modifiers(device, size, config.format, &config.modifiers, config.usage)
+        }));
+        built.config.usage = built.value.usage;
+        Ok(built)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Options;
+    use {Format, Modifier, RENDERING, SCANOUT};
+    use error::Error;
+
+    #[test]
+    fn candidates() {
+        let tiled = Modifier(0x0100_0000_0000_0001);
+        let mut options = Options::new();
+        match options.candidates() {
+            Err(Error::MissingParameter("format")) => (),
+            _ => panic!("a builder without a format has candidates")
+        }
+
+        // Without modifiers, every format gets a layout chosen by the driver.
+        options.format = Some(Format::XRGB8888);
+        options.fallback_formats = vec![Format::RGB565];
+        options.usage = RENDERING;
+        let candidates = options.candidates().unwrap();
+        let tried: Vec<_> = candidates.iter().map(|config| (config.format, config.modifiers.clone())).collect();
+        assert_eq!(tried, vec![(Format::XRGB8888, vec![]), (Format::RGB565, vec![])]);
+        assert!(candidates.iter().all(|config| config.usage == RENDERING));
+
+        // Explicit modifiers are tried alone unless INVALID allows falling back.
+        options.modifiers = vec![tiled, Modifier::LINEAR];
+        let tried: Vec<_> = options.candidates().unwrap().into_iter().map(|config| (config.format, config.modifiers)).collect();
+        assert_eq!(tried, vec![(Format::XRGB8888, vec![tiled, Modifier::LINEAR]), (Format::RGB565, vec![tiled, Modifier::LINEAR])]);
+
+        options.modifiers = vec![tiled, Modifier::INVALID];
+        let tried: Vec<_> = options.candidates().unwrap().into_iter().map(|config| (config.format, config.modifiers)).collect();
+        assert_eq!(tried, vec![
+            (Format::XRGB8888, vec![tiled]), (Format::XRGB8888, vec![]),
+            (Format::RGB565, vec![tiled]), (Format::RGB565, vec![])
+        ]);
+    }
+
+    #[test]
+    fn fallback_usages() {
+        let mut options = Options::new();
+        options.format = Some(Format::XRGB8888);
+        options.fallback_formats = vec![Format::RGB565];
+        options.usage = SCANOUT | RENDERING;
+        options.fallback_usages = vec![RENDERING];
+
+        // Every format is tried for scanout before settling for rendering.
+        let tried: Vec<_> = options.candidates().unwrap().into_iter().map(|config| (config.usage, config.format)).collect();
+        assert_eq!(tried, vec![
+            (SCANOUT | RENDERING, Format::XRGB8888), (SCANOUT | RENDERING, Format::RGB565),
+            (RENDERING, Format::XRGB8888), (RENDERING, Format::RGB565)
+        ]);
+
+        // The first error is returned if nothing works, and the result can
+        // be printed.
+        options.size = Some((16, 16));
+        let built = options.build(|_, config| if config.usage.contains(SCANOUT) { Err(Error::UnsupportedFlags(SCANOUT)) } else { Ok(()) });
+        assert!(format!("{:?}", built).contains("Built"));
+        assert_eq!(built.unwrap().config.usage, RENDERING);
+        match options.build(|_, _| Err::<(), _>(Error::UnsupportedFlags(SCANOUT))).unwrap_err() {
+            Error::UnsupportedFlags(flags) => assert_eq!(flags, SCANOUT),
+            other => panic!("expected the first error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn missing_size() {
+        let mut options = Options::new();
+        options.format = Some(Format::XRGB8888);
+        match options.build(|_, _| Ok(())) {
+            Err(Error::MissingParameter("size")) => (),
+            _ => panic!("a builder without a size built something")
+        }
+
+        options.size = Some((16, 16));
+        let built = options.build(|size, config| Ok((size, config.format))).unwrap();
+        assert_eq!(built.value, ((16, 16), Format::XRGB8888));
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs::File;
+
+use super::{Device, Buffer, Format, CURSOR, WRITE};
+use error::{Result, Error};
+
+/// The cursor size used when the driver's cursor caps are not known.
+pub const DEFAULT_SIZE: (u32, u32) = (64, 64);
+
+/// The number of buffers a `Cursor` cycles through by default.
+pub const DEFAULT_BUFFERS: usize = 3;
+
+/// The fewest buffers a `Cursor` cycles through, so that an upload never
+/// overwrites the buffer being scanned out.
+pub const MIN_BUFFERS: usize = 2;
+
+/// A `Cursor` is a small set of `CURSOR | WRITE` buffers holding a hardware
+/// cursor image and its hotspot.
+///
+/// Every image upload goes to the buffer after the current one, so the
+/// buffer that is being scanned out is never overwritten while animating.
+pub struct Cursor<'a, F> where F: 'a + AsRef<File> {
+    buffers: Vec<Buffer<'a, F>>,
+    current: usize,
+    hotspot: (u32, u32)
+}
+
+impl<'a, F> Cursor<'a, F> where F: AsRef<File> {
+    /// Creates a cursor from a `Device`, allocating `count` buffers of the
+    /// given size. At least `MIN_BUFFERS` buffers are allocated.
+    ///
+    /// The size should come from the DRM cursor width and height caps when
+    /// they are available, otherwise `DEFAULT_SIZE` is a safe choice.
+    pub fn from_device(device: &'a Device<F>, size: (u32, u32), count: usize) -> Result<Cursor<'a, F>> {
+        let count = count.max(MIN_BUFFERS);
+        let mut buffers = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut buffer = try!(device.buffer(size, Format::ARGB8888, CURSOR | WRITE));
+            try!(clear(&mut buffer));
+            buffers.push(buffer);
+        }
+
+        let cursor = Cursor {
+            buffers: buffers,
+            current: 0,
+            hotspot: (0, 0)
+        };
+        Ok(cursor)
+    }
+
+    /// Uploads a new cursor image and returns the buffer holding it.
+    ///
+    /// `image` holds tightly packed rows of non-premultiplied RGBA pixels,
+    /// `size` is its width and height, and `hotspot` is the pixel that
+    /// points at the cursor position, which must lie within the cursor. The
+    /// image is converted to premultiplied ARGB8888 and the area of the
+    /// buffer it does not cover is cleared.
+    pub fn set_image(&mut self, image: &[u8], size: (u32, u32), hotspot: (u32, u32)) -> Result<&Buffer<'a, F>> {
+        let next = next(self.current, self.buffers.len());
+        {
+            let max = self.size();
+            let buffer = &mut self.buffers[next];
+            let data = try!(render(image, size, hotspot, max, buffer.stride()));
+            try!(buffer.write(&data));
+        }
+
+        self.current = next;
+        self.hotspot = hotspot;
+        Ok(&self.buffers[next])
+    }
+
+    /// Clears the cursor image, making the cursor invisible.
+    pub fn clear(&mut self) -> Result<&Buffer<'a, F>> {
+        let next = next(self.current, self.buffers.len());
+        try!(clear(&mut self.buffers[next]));
+        self.current = next;
+        self.hotspot = (0, 0);
+        Ok(&self.buffers[next])
+    }
+
+    /// Returns the buffer holding the current cursor image.
+    pub fn buffer(&self) -> &Buffer<'a, F> {
+        &self.buffers[self.current]
+    }
+
+    /// Returns the hotspot of the current cursor image.
+    pub fn hotspot(&self) -> (u32, u32) {
+        self.hotspot
+    }
+
+    /// Returns the width and height of the cursor buffers.
+    pub fn size(&self) -> (u32, u32) {
+        self.buffers[0].size()
+    }
+}
+
+/// Returns the buffer an upload goes to, which is never the current one.
+fn next(current: usize, count: usize) -> usize {
+    (current + 1) % count
+}
+
+/// Fills a buffer with transparent pixels.
+fn clear<F>(buffer: &mut Buffer<F>) -> Result<()> where F: AsRef<File> {
+    let len = buffer.stride() as usize * buffer.size().1 as usize;
+    buffer.write(&vec![0u8; len])
+}
+
+/// Converts an image for `Cursor::set_image` into the contents of a whole
+/// cursor buffer of size `max` and the given stride.
+fn render(image: &[u8], size: (u32, u32), hotspot: (u32, u32), max: (u32, u32), stride: u32) -> Result<Vec<u8>> {
+    let (width, height) = size;
+    if width > max.0 || height > max.1 {
+        return Err(Error::TooLarge { max: max, actual: size });
+    }
+    if hotspot.0 >= max.0 || hotspot.1 >= max.1 {
+        return Err(Error::InvalidHotspot { size: max, hotspot: hotspot });
+    }
+
+    let row = width as usize * 4;
+    let expected = row * height as usize;
+    if image.len() != expected {
+        return Err(Error::InvalidLength { expected: expected, actual: image.len() });
+    }
+
+    let stride = stride as usize;
+    let mut data = vec![0u8; stride * max.1 as usize];
+    for (src, dst) in image.chunks(row).zip(data.chunks_mut(stride)) {
+        for (pixel, out) in src.chunks(4).zip(dst.chunks_mut(4)) {
+            premultiply(pixel, out);
+        }
+    }
+    Ok(data)
+}
+
+/// Converts a non-premultiplied RGBA pixel to premultiplied ARGB8888, which
+/// is stored as B, G, R, A in memory.
+fn premultiply(src: &[u8], dst: &mut [u8]) {
+    let alpha = src[3] as u32;
+    let scale = |c: u8| ((c as u32 * alpha + 127) / 255) as u8;
+    dst[0] = scale(src[2]);
+    dst[1] = scale(src[1]);
+    dst[2] = scale(src[0]);
+    dst[3] = src[3];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next, premultiply, render, MIN_BUFFERS};
+
+    #[test]
+    fn premultiplied() {
+        let mut out = [0; 4];
+        premultiply(&[0xff, 0x80, 0x00, 0xff], &mut out);
+        assert_eq!(out, [0x00, 0x80, 0xff, 0xff]);
+        premultiply(&[0xff, 0x80, 0x00, 0x80], &mut out);
+        assert_eq!(out, [0x00, 0x40, 0x80, 0x80]);
+        premultiply(&[0xff, 0xff, 0xff, 0x00], &mut out);
+        assert_eq!(out, [0; 4]);
+    }
+
+    #[test]
+    fn rendered_image() {
+        let image = [0xff; 2 * 2 * 4];
+        let data = render(&image, (2, 2), (1, 1), (4, 4), 32).unwrap();
+        assert_eq!(data.len(), 32 * 4);
+
+        // The image is placed at the top left, and the rest is cleared.
+        for (y, row) in data.chunks(32).enumerate() {
+            let covered = if y < 2 { 8 } else { 0 };
+            assert!(row[..covered].iter().all(|&b| b == 0xff));
+            assert!(row[covered..].iter().all(|&b| b == 0));
+        }
+
+        assert!(render(&[0; 8 * 8 * 4], (8, 8), (0, 0), (4, 4), 32).is_err());
+        assert!(render(&image, (2, 2), (4, 0), (4, 4), 32).is_err());
+        assert!(render(&image, (2, 2), (0, 4), (4, 4), 32).is_err());
+        assert!(render(&image[..4], (2, 2), (0, 0), (4, 4), 32).is_err());
+    }
+
+    #[test]
+    fn ring() {
+        for count in MIN_BUFFERS..5 {
+            let mut current = 0;
+            let mut seen = vec![false; count];
+            for _ in 0..count {
+                let upload = next(current, count);
+                assert!(upload != current);
+                seen[upload] = true;
+                current = upload;
+            }
+            assert!(seen.iter().all(|&seen| seen));
+        }
+    }
+}
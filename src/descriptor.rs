@@ -0,0 +1,58 @@
+use super::{BufferFlags, Format, Modifier, Plane};
+
+/// Everything about a dma-buf backed buffer apart from its file
+/// descriptors: what `Buffer::descriptor` exports, `Device::import_dmabuf`
+/// imports and the `ipc` module sends.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DmabufDescriptor {
+    /// The width of the buffer, in pixels.
+    pub width: u32,
+    /// The height of the buffer, in pixels.
+    pub height: u32,
+    /// The format of the buffer.
+    pub format: Format,
+    /// The format modifier, or `Modifier::INVALID` for an implicit one.
+    pub modifier: Modifier,
+    /// The offset and stride of each plane.
+    pub planes: Vec<Plane>,
+    /// How the buffer is used.
+    #[cfg_attr(feature = "serde", serde(with = "usage"))]
+    pub usage: BufferFlags
+}
+
+impl DmabufDescriptor {
+    /// Describes a buffer with a single linear plane at the start of its
+    /// dma-buf, such as a software or dumb buffer.
+    pub fn linear(size: (u32, u32), format: Format, stride: u32, usage: BufferFlags) -> DmabufDescriptor {
+        DmabufDescriptor {
+            width: size.0,
+            height: size.1,
+            format: format,
+            modifier: Modifier::LINEAR,
+            planes: vec![Plane { offset: 0, stride: stride }],
+            usage: usage
+        }
+    }
+
+    /// Returns the width and height of the buffer.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+// `BufferFlags` has no serde support, so it is serialized as its bits.
+#[cfg(feature = "serde")]
+mod usage {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::super::BufferFlags;
+
+    pub fn serialize<S>(usage: &BufferFlags, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_u32(usage.bits())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BufferFlags, D::Error> where D: Deserializer<'de> {
+        u32::deserialize(deserializer).map(BufferFlags::from_bits_truncate)
+    }
+}
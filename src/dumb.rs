@@ -0,0 +1,461 @@
+//! An allocator using DRM dumb buffers.
+//!
+//! Dumb buffers are linear buffers that every KMS driver can create, map
+//! and scan out, including virtual drivers that libgbm does not support.
+//! When libgbm cannot be loaded, or refuses a format, a `dumb::Device` can
+//! be created on the same file to fall back on, either directly with
+//! `dumb::Device::from_file` or from a libgbm `Device` with `Device::dumb`.
+
+use errno::{Errno, errno};
+use libc;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::raw::{c_ulong, c_void};
+use std::ptr::null_mut;
+use std::slice;
+
+use super::{Allocator, BufferObject, BufferFlags, DmabufDescriptor, Format, Modifier, TransferFlags};
+use error::{Result, Error};
+
+#[repr(C)]
+#[derive(Default)]
+struct drm_mode_create_dumb {
+    height: u32,
+    width: u32,
+    bpp: u32,
+    flags: u32,
+    handle: u32,
+    pitch: u32,
+    size: u64
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct drm_mode_map_dumb {
+    handle: u32,
+    pad: u32,
+    offset: u64
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct drm_mode_destroy_dumb {
+    handle: u32
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct drm_gem_close {
+    handle: u32,
+    pad: u32
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct drm_prime_handle {
+    handle: u32,
+    flags: u32,
+    fd: i32
+}
+
+const IOC_WRITE: c_ulong = 1;
+const IOC_READ: c_ulong = 2;
+
+/// Encodes a DRM ioctl request number with the size of its argument.
+const fn drm_ioctl<T>(dir: c_ulong, nr: c_ulong) -> c_ulong {
+    dir << 30 | (mem::size_of::<T>() as c_ulong) << 16 | (b'd' as c_ulong) << 8 | nr
+}
+
+const DRM_IOCTL_GEM_CLOSE: c_ulong = drm_ioctl::<drm_gem_close>(IOC_WRITE, 0x09);
+const DRM_IOCTL_PRIME_HANDLE_TO_FD: c_ulong = drm_ioctl::<drm_prime_handle>(IOC_READ | IOC_WRITE, 0x2d);
+const DRM_IOCTL_PRIME_FD_TO_HANDLE: c_ulong = drm_ioctl::<drm_prime_handle>(IOC_READ | IOC_WRITE, 0x2e);
+const DRM_IOCTL_MODE_CREATE_DUMB: c_ulong = drm_ioctl::<drm_mode_create_dumb>(IOC_READ | IOC_WRITE, 0xb2);
+const DRM_IOCTL_MODE_MAP_DUMB: c_ulong = drm_ioctl::<drm_mode_map_dumb>(IOC_READ | IOC_WRITE, 0xb3);
+const DRM_IOCTL_MODE_DESTROY_DUMB: c_ulong = drm_ioctl::<drm_mode_destroy_dumb>(IOC_READ | IOC_WRITE, 0xb4);
+
+/// Issues an ioctl, retrying when it is interrupted.
+fn ioctl<T>(fd: RawFd, request: c_ulong, arg: &mut T) -> Result<()> {
+    loop {
+        let ret = unsafe { libc::ioctl(fd, request as _, arg as *mut T as *mut c_void) };
+        if ret == 0 {
+            return Ok(());
+        }
+
+        let err = errno();
+        if err != Errno(libc::EINTR) && err != Errno(libc::EAGAIN) {
+            return Err(Error::Ioctl(err));
+        }
+    }
+}
+
+/// Counts the buffers using each GEM handle of a device.
+///
+/// Importing a dma-buf the device already has a handle for, such as one
+/// imported or exported before, returns the same handle again, so it may
+/// only be closed once the last buffer using it is dropped.
+#[derive(Debug, Default)]
+struct Handles {
+    counts: RefCell<HashMap<u32, usize>>
+}
+
+impl Handles {
+    fn acquire(&self, handle: u32) {
+        *self.counts.borrow_mut().entry(handle).or_insert(0) += 1;
+    }
+
+    /// Returns true if the handle is no longer used and should be closed.
+    fn release(&self, handle: u32) -> bool {
+        let mut counts = self.counts.borrow_mut();
+        let last = match counts.get_mut(&handle) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            },
+            None => true
+        };
+        if last {
+            counts.remove(&handle);
+        }
+        last
+    }
+}
+
+/// A `Device` allocating DRM dumb buffers on a DRM device file.
+///
+/// Like the libgbm `Device`, it does not own the file. To share one file
+/// with a libgbm `Device`, use `Device::dumb` or a shared handle such as
+/// `Rc<File>`.
+pub struct Device<F> where F: AsRef<File> {
+    file: F,
+    handles: Handles
+}
+
+impl<F> AsRef<File> for Device<F> where F: AsRef<File> {
+    fn as_ref(&self) -> &File {
+        self.file.as_ref()
+    }
+}
+
+impl<'a, F> Device<F> where F: AsRef<File> {
+    /// Creates a `Device` from a file reference.
+    pub fn from_file(file: F) -> Device<F> {
+        Device {
+            file: file,
+            handles: Handles::default()
+        }
+    }
+
+    fn fd(&self) -> RawFd {
+        self.file.as_ref().as_raw_fd()
+    }
+
+    /// Creates a `Buffer` using the given size and parameters.
+    ///
+    /// Only formats with a single plane are supported.
+    pub fn buffer(&'a self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Buffer<'a, F>> {
+        let cpp = try!(format.bytes_per_pixel().ok_or(Error::UnsupportedFormat(format)));
+        let mut create = drm_mode_create_dumb {
+            width: size.0,
+            height: size.1,
+            bpp: cpp * 8,
+            ..Default::default()
+        };
+        try!(ioctl(self.fd(), DRM_IOCTL_MODE_CREATE_DUMB, &mut create));
+        self.handles.acquire(create.handle);
+
+        let buffer = Buffer {
+            device: self,
+            handle: create.handle,
+            size: size,
+            stride: create.pitch,
+            format: format,
+            usage: flags,
+            imported: false
+        };
+        Ok(buffer)
+    }
+
+    /// Imports a dma-buf as a `Buffer` using PRIME, with the layout and
+    /// usage of a descriptor with a single linear plane.
+    ///
+    /// Buffers sharing the memory of one dma-buf share its GEM handle, which
+    /// is closed when the last of them is dropped. Fails if the dma-buf is
+    /// too small for the described rows.
+    pub fn import_dmabuf(&'a self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Buffer<'a, F>> {
+        let (fd, stride) = try!(super::linear_plane(descriptor, fds));
+        let len = try!((stride as usize).checked_mul(descriptor.height as usize).ok_or(Error::InvalidSize(descriptor.size())));
+        let available = try!(super::file_len(fd));
+        if available < len as u64 {
+            return Err(Error::InvalidLength { expected: len, actual: available as usize });
+        }
+
+        let mut prime = drm_prime_handle {
+            fd: fd.as_raw_fd(),
+            ..Default::default()
+        };
+        try!(ioctl(self.fd(), DRM_IOCTL_PRIME_FD_TO_HANDLE, &mut prime));
+        self.handles.acquire(prime.handle);
+
+        let buffer = Buffer {
+            device: self,
+            handle: prime.handle,
+            size: descriptor.size(),
+            stride: stride,
+            format: descriptor.format,
+            usage: descriptor.usage,
+            imported: true
+        };
+        Ok(buffer)
+    }
+}
+
+/// A DRM dumb buffer.
+pub struct Buffer<'a, F> where F: 'a + AsRef<File> {
+    device: &'a Device<F>,
+    handle: u32,
+    size: (u32, u32),
+    stride: u32,
+    format: Format,
+    usage: BufferFlags,
+    imported: bool
+}
+
+impl<'a, F> Buffer<'a, F> where F: AsRef<File> {
+    /// Returns the width and height of the buffer.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Returns the stride of the buffer.
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+
+    /// Returns the format of the buffer.
+    pub fn format(&self) -> u32 {
+        self.format as u32
+    }
+
+    /// Returns the usage flags the buffer was created with.
+    pub fn usage(&self) -> BufferFlags {
+        self.usage
+    }
+
+    /// Returns the GEM handle of the buffer.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// Returns the format modifier of the buffer, which is always linear.
+    pub fn modifier(&self) -> Result<Modifier> {
+        Ok(Modifier::LINEAR)
+    }
+
+    /// Returns the number of planes in the buffer, which is always one.
+    pub fn plane_count(&self) -> Result<u32> {
+        Ok(1)
+    }
+
+    /// Describes the buffer for importing its dma-buf elsewhere.
+    pub fn descriptor(&self) -> DmabufDescriptor {
+        DmabufDescriptor::linear(self.size(), self.format, self.stride(), self.usage)
+    }
+
+    /// Exports the buffer as a dma-buf file descriptor using PRIME.
+    pub fn fd(&self) -> Result<OwnedFd> {
+        let mut prime = drm_prime_handle {
+            handle: self.handle,
+            flags: (libc::O_CLOEXEC | libc::O_RDWR) as u32,
+            ..Default::default()
+        };
+        try!(ioctl(self.device.fd(), DRM_IOCTL_PRIME_HANDLE_TO_FD, &mut prime));
+        Ok(unsafe { OwnedFd::from_raw_fd(prime.fd) })
+    }
+
+    /// Maps the whole buffer into memory for CPU access.
+    ///
+    /// Dumb buffers are always mapped for reading and writing. The buffer is
+    /// unmapped when the returned `Mapping` is dropped.
+    pub fn map(&mut self, _flags: TransferFlags) -> Result<Mapping<'_>> {
+        let mut map = drm_mode_map_dumb {
+            handle: self.handle,
+            ..Default::default()
+        };
+        try!(ioctl(self.device.fd(), DRM_IOCTL_MODE_MAP_DUMB, &mut map));
+
+        let len = self.stride as usize * self.size.1 as usize;
+        let data = unsafe {
+            libc::mmap(null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED,
+                       self.device.fd(), map.offset as libc::off_t)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let mapping = Mapping {
+            data: unsafe { slice::from_raw_parts_mut(data as *mut u8, len) },
+            stride: self.stride
+        };
+        Ok(mapping)
+    }
+}
+
+impl<'a, F> Drop for Buffer<'a, F> where F: AsRef<File> {
+    fn drop(&mut self) {
+        if !self.device.handles.release(self.handle) {
+            return;
+        }
+        let fd = self.device.fd();
+        let _ = if self.imported {
+            ioctl(fd, DRM_IOCTL_GEM_CLOSE, &mut drm_gem_close { handle: self.handle, pad: 0 })
+        } else {
+            ioctl(fd, DRM_IOCTL_MODE_DESTROY_DUMB, &mut drm_mode_destroy_dumb { handle: self.handle })
+        };
+    }
+}
+
+impl<'a, F> BufferObject for Buffer<'a, F> where F: AsRef<File> {
+    fn size(&self) -> (u32, u32) {
+        Buffer::size(self)
+    }
+
+    fn stride(&self) -> u32 {
+        Buffer::stride(self)
+    }
+
+    fn format(&self) -> u32 {
+        Buffer::format(self)
+    }
+
+    fn descriptor(&self) -> Result<DmabufDescriptor> {
+        Ok(Buffer::descriptor(self))
+    }
+}
+
+/// A CPU mapping of a dumb `Buffer`.
+pub struct Mapping<'b> {
+    data: &'b mut [u8],
+    stride: u32
+}
+
+impl<'b> Mapping<'b> {
+    /// Returns the stride of the mapped memory.
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+}
+
+impl<'b> Deref for Mapping<'b> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'b> DerefMut for Mapping<'b> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+}
+
+impl<'b> Drop for Mapping<'b> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.data.as_mut_ptr() as *mut c_void, self.data.len()) };
+    }
+}
+
+impl<F> Allocator for Device<F> where F: AsRef<File> {
+    type Buffer<'a> = Buffer<'a, F> where Self: 'a;
+
+    fn create_buffer(&self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Buffer<'_, F>> {
+        self.buffer(size, format, flags)
+    }
+
+    fn import_buffer(&self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Buffer<'_, F>> {
+        self.import_dmabuf(descriptor, fds)
+    }
+
+    fn export_buffer(&self, buffer: &Buffer<'_, F>) -> Result<Vec<OwnedFd>> {
+        Ok(vec![try!(buffer.fd())])
+    }
+
+    fn map_buffer<R, M>(&self, buffer: &mut Buffer<'_, F>, func: M) -> Result<R>
+        where M: FnOnce(&mut [u8], u32) -> R
+    {
+        let mut mapping = try!(buffer.map(super::TRANSFER_READ_WRITE));
+        let stride = mapping.stride();
+        Ok(func(&mut mapping, stride))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ioctl_encoding() {
+        assert_eq!(mem::size_of::<drm_mode_create_dumb>(), 32);
+        assert_eq!(mem::size_of::<drm_mode_map_dumb>(), 16);
+        assert_eq!(mem::size_of::<drm_mode_destroy_dumb>(), 4);
+        assert_eq!(mem::size_of::<drm_gem_close>(), 8);
+        assert_eq!(mem::size_of::<drm_prime_handle>(), 12);
+
+        assert_eq!(DRM_IOCTL_MODE_CREATE_DUMB, 0xc020_64b2);
+        assert_eq!(DRM_IOCTL_MODE_MAP_DUMB, 0xc010_64b3);
+        assert_eq!(DRM_IOCTL_MODE_DESTROY_DUMB, 0xc004_64b4);
+        assert_eq!(DRM_IOCTL_PRIME_HANDLE_TO_FD, 0xc00c_642d);
+        assert_eq!(DRM_IOCTL_PRIME_FD_TO_HANDLE, 0xc00c_642e);
+        assert_eq!(DRM_IOCTL_GEM_CLOSE, 0x4008_6409);
+    }
+
+    #[test]
+    fn shared_handles() {
+        let handles = Handles::default();
+        handles.acquire(1);
+        handles.acquire(2);
+        handles.acquire(1);
+
+        // The handle imported twice stays open until both buffers are gone.
+        assert!(!handles.release(1));
+        assert!(handles.release(2));
+        assert!(handles.release(1));
+
+        handles.acquire(1);
+        assert!(handles.release(1));
+    }
+
+    struct Null(File);
+
+    impl AsRef<File> for Null {
+        fn as_ref(&self) -> &File {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn short_dmabuf() {
+        use std::os::unix::io::AsFd;
+        use software;
+
+        // The length is checked before the dma-buf reaches the kernel, so
+        // any file will do as the device.
+        let device = Device::from_file(Null(File::open("/dev/null").unwrap()));
+        let source = software::Device::new();
+        let buffer = source.buffer((8, 8), Format::XRGB8888, super::super::RENDERING).unwrap();
+        let fd = buffer.fd().unwrap();
+
+        let mut descriptor = buffer.descriptor();
+        descriptor.height = 64;
+        match device.import_dmabuf(&descriptor, &[fd.as_fd()]) {
+            Err(Error::InvalidLength { expected, .. }) => assert_eq!(expected, buffer.stride() as usize * 64),
+            _ => panic!("a short dma-buf was imported")
+        };
+    }
+}
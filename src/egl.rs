@@ -0,0 +1,360 @@
+//! EGL integration on the GBM platform.
+//!
+//! A `Display` is the EGL display of a `Device`, from which window surfaces
+//! are created on top of a libgbm `Surface`. EGL configs are picked by
+//! their native visual, which on this platform is the fourcc code of the
+//! `Format` the surface was created with. A config with another visual
+//! fails to create a surface or renders with the wrong channel layout.
+//!
+//! Buffers can be turned into an `Image` with `Display::create_image`, to
+//! be bound as a texture without copying. In the other direction, an image
+//! rendered with GL becomes a `Buffer` with `Display::export_image`.
+//!
+//! The EGL library is loaded by the caller through `khronos_egl`, either
+//! linked or loaded at runtime, and must provide EGL 1.5.
+
+use khronos_egl::{self as khr, Attrib, Config, Instance, Int};
+use khronos_egl::api::EGL1_5;
+
+use std::fs::File;
+use std::marker::PhantomData;
+use std::mem::transmute;
+use std::os::raw::c_int;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr::null_mut;
+
+use super::{Buffer, BufferFlags, Device, DmabufDescriptor, Format, Modifier, Plane, Surface};
+use error::{Result, Error};
+
+/// The platform of displays created from a `gbm_device`.
+pub const PLATFORM_GBM_KHR: khr::Enum = 0x31D7;
+
+/// The image target for dma-bufs, from `EGL_EXT_image_dma_buf_import`.
+pub const LINUX_DMA_BUF_EXT: khr::Enum = 0x3270;
+
+const LINUX_DRM_FOURCC_EXT: Attrib = 0x3271;
+
+/// The file descriptor, offset and pitch attributes of each plane.
+const PLANE_ATTRIBUTES: [[Attrib; 3]; 4] = [
+    [0x3272, 0x3273, 0x3274],
+    [0x3275, 0x3276, 0x3277],
+    [0x3278, 0x3279, 0x327A],
+    [0x3440, 0x3441, 0x3442]
+];
+
+/// The low and high modifier attributes of each plane, from
+/// `EGL_EXT_image_dma_buf_import_modifiers`.
+const MODIFIER_ATTRIBUTES: [[Attrib; 2]; 4] = [
+    [0x3443, 0x3444],
+    [0x3445, 0x3446],
+    [0x3447, 0x3448],
+    [0x3449, 0x344A]
+];
+
+type ExportDmabufImageQuery = unsafe extern "system" fn(khr::EGLDisplay, khr::EGLImage, *mut c_int, *mut c_int, *mut u64) -> khr::Boolean;
+type ExportDmabufImage = unsafe extern "system" fn(khr::EGLDisplay, khr::EGLImage, *mut c_int, *mut khr::Int, *mut khr::Int) -> khr::Boolean;
+
+/// The EGL display of a `Device`.
+///
+/// EGL keeps a single display per device, so it is not terminated when
+/// the `Display` is dropped.
+pub struct Display<'a, T, F> where T: 'a + EGL1_5, F: 'a + AsRef<File> {
+    egl: &'a Instance<T>,
+    device: &'a Device<F>,
+    raw: khr::Display,
+    version: (Int, Int)
+}
+
+impl<'a, T, F> Display<'a, T, F> where T: EGL1_5, F: AsRef<File> {
+    /// Gets and initializes the EGL display of a device.
+    pub fn new(egl: &'a Instance<T>, device: &'a Device<F>) -> Result<Display<'a, T, F>> {
+        let raw = try!(unsafe { egl.get_platform_display(PLATFORM_GBM_KHR, device.raw(), &[khr::ATTRIB_NONE]) });
+        let version = try!(egl.initialize(raw));
+        let display = Display {
+            egl: egl,
+            device: device,
+            raw: raw,
+            version: version
+        };
+        Ok(display)
+    }
+
+    /// Returns the EGL version implemented for the display.
+    pub fn version(&self) -> (Int, Int) {
+        self.version
+    }
+
+    /// Returns the device of the display.
+    pub fn device(&self) -> &'a Device<F> {
+        self.device
+    }
+
+    /// Returns the EGL instance the display was created with.
+    pub fn egl(&self) -> &'a Instance<T> {
+        self.egl
+    }
+
+    /// Returns true if the display supports an EGL extension.
+    pub fn has_extension(&self, name: &str) -> bool {
+        match self.egl.query_string(Some(self.raw), khr::EXTENSIONS) {
+            Ok(extensions) => extensions.to_string_lossy().split(' ').any(|ext| ext == name),
+            Err(_) => false
+        }
+    }
+
+    /// Returns the configs matching the attributes whose native visual is
+    /// `format`, in the order EGL sorts them.
+    ///
+    /// Like every `khronos_egl` attribute list, `attributes` must end with
+    /// `NONE`. Window surfaces need `SURFACE_TYPE` to include `WINDOW_BIT`.
+    pub fn configs(&self, attributes: &[Int], format: Format) -> Result<Vec<Config>> {
+        let count = try!(self.egl.matching_config_count(self.raw, attributes));
+        let mut configs = Vec::with_capacity(count);
+        try!(self.egl.choose_config(self.raw, attributes, &mut configs));
+
+        let mut matching = Vec::new();
+        for config in configs {
+            let visual = try!(self.egl.get_config_attrib(self.raw, config, khr::NATIVE_VISUAL_ID));
+            if visual as u32 == format as u32 {
+                matching.push(config);
+            }
+        }
+        Ok(matching)
+    }
+
+    /// Returns the best config matching the attributes whose native visual
+    /// is `format`, see `configs`.
+    pub fn choose_config(&self, attributes: &[Int], format: Format) -> Result<Option<Config>> {
+        self.configs(attributes, format).map(|configs| configs.into_iter().next())
+    }
+
+    /// Creates a window surface rendering into a libgbm `Surface`.
+    ///
+    /// The config should come from `choose_config` with the surface's
+    /// format. `attributes` must end with `ATTRIB_NONE`.
+    ///
+    /// # Safety
+    /// The surface must have been created from the display's `Device`, and
+    /// must outlive the returned EGL surface.
+    pub unsafe fn create_window_surface(&self, config: Config, surface: &Surface<F>, attributes: &[Attrib]) -> Result<khr::Surface> {
+        let surface = try!(self.egl.create_platform_window_surface(self.raw, config, surface.raw(), attributes));
+        Ok(surface)
+    }
+
+    /// Creates an `Image` sharing the memory of a buffer, using
+    /// `EGL_EXT_image_dma_buf_import`.
+    ///
+    /// The modifier is passed along when the buffer has an explicit one and
+    /// the display supports `EGL_EXT_image_dma_buf_import_modifiers`, which
+    /// only linear buffers and buffers with an implicit modifier can do
+    /// without. The image is destroyed when dropped, and cannot outlive the
+    /// buffer.
+    pub fn create_image<'i, 'b, G>(&'i self, buffer: &'i Buffer<'b, G>) -> Result<Image<'i, 'a, T, F>> where G: AsRef<File> {
+        let descriptor = try!(buffer.descriptor());
+        let modifiers = try!(import_modifiers(descriptor.modifier, |name| self.has_extension(name)));
+        // EGL takes its own references, so the descriptors are closed at the
+        // end of this function.
+        let fds = try!(buffer.plane_fds());
+        let raw_fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+        let attributes = try!(image_attributes(&descriptor, &raw_fds, modifiers));
+
+        let client_buffer = unsafe { khr::ClientBuffer::from_ptr(null_mut()) };
+        let context = unsafe { khr::Context::from_ptr(khr::NO_CONTEXT) };
+        let raw = try!(self.egl.create_image(self.raw, context, LINUX_DMA_BUF_EXT, client_buffer, &attributes));
+        let image = Image {
+            display: self,
+            raw: raw,
+            buffer: PhantomData
+        };
+        Ok(image)
+    }
+
+    /// Exports an image as dma-bufs with `EGL_MESA_image_dma_buf_export` and
+    /// imports them into the display's `Device`.
+    ///
+    /// EGL does not report the size of an image, so it is given here. The
+    /// format, modifier and plane layout come from the export.
+    pub fn export_image(&self, image: khr::Image, size: (u32, u32), flags: BufferFlags) -> Result<Buffer<'a, F>> {
+        let extension = "EGL_MESA_image_dma_buf_export";
+        if !self.has_extension(extension) {
+            return Err(Error::Unsupported(extension));
+        }
+        let (query, export): (ExportDmabufImageQuery, ExportDmabufImage) = unsafe {
+            match (self.egl.get_proc_address("eglExportDMABUFImageQueryMESA"),
+                   self.egl.get_proc_address("eglExportDMABUFImageMESA")) {
+                (Some(query), Some(export)) => (transmute::<extern "system" fn(), ExportDmabufImageQuery>(query),
+                                                 transmute::<extern "system" fn(), ExportDmabufImage>(export)),
+                _ => return Err(Error::Unsupported(extension))
+            }
+        };
+
+        let (mut fourcc, mut count) = (0, 0);
+        try!(self.check(unsafe { query(self.raw.as_ptr(), image.as_ptr(), &mut fourcc, &mut count, null_mut()) }));
+        let count = count as usize;
+        if count == 0 || count > PLANE_ATTRIBUTES.len() {
+            return Err(Error::InvalidPlaneCount { expected: PLANE_ATTRIBUTES.len(), actual: count });
+        }
+        let mut modifiers = vec![0; count];
+        try!(self.check(unsafe { query(self.raw.as_ptr(), image.as_ptr(), &mut fourcc, null_mut(), modifiers.as_mut_ptr()) }));
+        // The modifier is reported per plane, but a buffer has a single one
+        // for all of its planes, as every driver lays them out.
+        if modifiers.iter().any(|&modifier| modifier != modifiers[0]) {
+            return Err(Error::Unsupported("images with a different modifier per plane"));
+        }
+        let format = try!(Format::from_fourcc(fourcc as u32).ok_or(Error::UnknownFormat(fourcc as u32)));
+
+        let mut raw_fds = vec![-1; count];
+        let mut strides = vec![0; count];
+        let mut offsets = vec![0; count];
+        try!(self.check(unsafe {
+            export(self.raw.as_ptr(), image.as_ptr(), raw_fds.as_mut_ptr(), strides.as_mut_ptr(), offsets.as_mut_ptr())
+        }));
+
+        // Planes stored in the same dma-buf as the previous one get no
+        // descriptor of their own.
+        let fds: Vec<Option<OwnedFd>> = raw_fds.iter()
+            .map(|&fd| if fd >= 0 { Some(unsafe { OwnedFd::from_raw_fd(fd) }) } else { None })
+            .collect();
+        let mut borrowed: Vec<BorrowedFd> = Vec::with_capacity(count);
+        for fd in &fds {
+            let plane_fd = match (fd.as_ref(), borrowed.last()) {
+                (Some(fd), _) => fd.as_fd(),
+                (None, Some(&previous)) => previous,
+                (None, None) => return Err(Error::InvalidPlaneCount { expected: count, actual: 0 })
+            };
+            borrowed.push(plane_fd);
+        }
+
+        let descriptor = DmabufDescriptor {
+            width: size.0,
+            height: size.1,
+            format: format,
+            modifier: Modifier(modifiers[0]),
+            planes: offsets.iter().zip(&strides)
+                .map(|(&offset, &stride)| Plane { offset: offset as u32, stride: stride as u32 })
+                .collect(),
+            usage: flags
+        };
+        self.device.import_dmabuf(&descriptor, &borrowed)
+    }
+
+    /// Turns the result of an extension call into the current EGL error.
+    fn check(&self, result: khr::Boolean) -> Result<()> {
+        if result == khr::TRUE {
+            Ok(())
+        } else {
+            Err(Error::Egl(self.egl.get_error().unwrap_or(khr::Error::BadAccess)))
+        }
+    }
+
+    /// Returns the underlying `EGLDisplay`.
+    pub fn raw(&self) -> khr::Display {
+        self.raw
+    }
+}
+
+/// Checks that a display with the extensions `has_extension` reports can
+/// import a buffer with the given modifier, and returns whether the modifier
+/// can be passed along.
+fn import_modifiers<H>(modifier: Modifier, has_extension: H) -> Result<bool> where H: Fn(&str) -> bool {
+    let (import, modifiers) = ("EGL_EXT_image_dma_buf_import", "EGL_EXT_image_dma_buf_import_modifiers");
+    if !has_extension(import) {
+        return Err(Error::Unsupported(import));
+    }
+    let explicit = modifier != Modifier::INVALID && modifier != Modifier::LINEAR;
+    if explicit && !has_extension(modifiers) {
+        return Err(Error::Unsupported(modifiers));
+    }
+    Ok(has_extension(modifiers))
+}
+
+/// Returns the attribute list of `EGL_EXT_image_dma_buf_import` for a
+/// buffer, ending with `ATTRIB_NONE`, with one file descriptor per plane.
+///
+/// The modifier is only included when it is explicit and `modifiers` says
+/// the display supports `EGL_EXT_image_dma_buf_import_modifiers`.
+pub fn image_attributes(descriptor: &DmabufDescriptor, fds: &[RawFd], modifiers: bool) -> Result<Vec<Attrib>> {
+    let planes = &descriptor.planes;
+    if planes.is_empty() || planes.len() > PLANE_ATTRIBUTES.len() {
+        return Err(Error::InvalidPlaneCount { expected: PLANE_ATTRIBUTES.len(), actual: planes.len() });
+    }
+    if fds.len() != planes.len() {
+        return Err(Error::InvalidPlaneCount { expected: planes.len(), actual: fds.len() });
+    }
+    let modifier = match descriptor.modifier {
+        Modifier::INVALID => None,
+        modifier => if modifiers { Some(modifier) } else { None }
+    };
+
+    let mut attributes = vec![
+        khr::WIDTH as Attrib, descriptor.width as Attrib,
+        khr::HEIGHT as Attrib, descriptor.height as Attrib,
+        LINUX_DRM_FOURCC_EXT, descriptor.format as Attrib
+    ];
+    for (index, (plane, &fd)) in planes.iter().zip(fds).enumerate() {
+        let [fd_attribute, offset_attribute, pitch_attribute] = PLANE_ATTRIBUTES[index];
+        attributes.extend_from_slice(&[
+            fd_attribute, fd as Attrib,
+            offset_attribute, plane.offset as Attrib,
+            pitch_attribute, plane.stride as Attrib
+        ]);
+        if let Some(modifier) = modifier {
+            let [lo_attribute, hi_attribute] = MODIFIER_ATTRIBUTES[index];
+            attributes.extend_from_slice(&[
+                lo_attribute, (modifier.0 & 0xffff_ffff) as Attrib,
+                hi_attribute, (modifier.0 >> 32) as Attrib
+            ]);
+        }
+    }
+    attributes.push(khr::ATTRIB_NONE);
+    Ok(attributes)
+}
+
+/// An `EGLImage` sharing the memory of a `Buffer`.
+pub struct Image<'i, 'a, T, F> where 'a: 'i, T: 'a + EGL1_5, F: 'a + AsRef<File> {
+    display: &'i Display<'a, T, F>,
+    raw: khr::Image,
+    buffer: PhantomData<&'i ()>
+}
+
+impl<'i, 'a, T, F> Image<'i, 'a, T, F> where T: EGL1_5, F: AsRef<File> {
+    /// Returns the underlying `EGLImage`, to be bound with
+    /// `glEGLImageTargetTexture2DOES`.
+    pub fn raw(&self) -> khr::Image {
+        self.raw
+    }
+}
+
+impl<'i, 'a, T, F> Drop for Image<'i, 'a, T, F> where T: EGL1_5, F: AsRef<File> {
+    fn drop(&mut self) {
+        let _ = self.display.egl.destroy_image(self.display.raw, self.raw);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::import_modifiers;
+    use Modifier;
+    use error::Error;
+
+    #[test]
+    fn import_extensions() {
+        let tiled = Modifier(0x0100_0000_0000_0001);
+        let none = |_: &str| false;
+        let import = |name: &str| name == "EGL_EXT_image_dma_buf_import";
+        let all = |_: &str| true;
+
+        match import_modifiers(Modifier::LINEAR, none) {
+            Err(Error::Unsupported("EGL_EXT_image_dma_buf_import")) => (),
+            other => panic!("imported without the extension: {:?}", other.is_ok())
+        }
+        // Tiled layouts cannot be described without the modifier.
+        match import_modifiers(tiled, import) {
+            Err(Error::Unsupported("EGL_EXT_image_dma_buf_import_modifiers")) => (),
+            other => panic!("imported a tiled buffer without modifiers: {:?}", other.is_ok())
+        }
+        assert!(!import_modifiers(Modifier::LINEAR, import).unwrap());
+        assert!(!import_modifiers(Modifier::INVALID, import).unwrap());
+        assert!(import_modifiers(tiled, all).unwrap());
+    }
+}
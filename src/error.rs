@@ -1,13 +1,52 @@
 use errno::Errno;
+#[cfg(feature = "egl")]
+use khronos_egl;
+#[cfg(feature = "x11")]
+use x11rb::errors::{ConnectionError, ReplyError};
 
 use std::fmt;
+use std::io;
 use std::error::Error as StdError;
 use std::result::Result as StdResult;
 use std::convert::From;
 
+use super::{BufferFlags, Format};
+
 #[derive(Debug)]
 pub enum Error {
     Ioctl(Errno),
+    /// The buffer was not created with the usage flags an operation needs.
+    MissingUsage(BufferFlags),
+    /// The data given does not match the size of the buffer, in bytes.
+    InvalidLength { expected: usize, actual: usize },
+    /// A buffer of this width and height would not fit in memory.
+    InvalidSize((u32, u32)),
+    /// An image is larger than the buffer it is uploaded to.
+    TooLarge { max: (u32, u32), actual: (u32, u32) },
+    /// A cursor hotspot lies outside the cursor.
+    InvalidHotspot { size: (u32, u32), hotspot: (u32, u32) },
+    /// The loaded libgbm does not understand these usage flags.
+    UnsupportedFlags(BufferFlags),
+    /// The format cannot be used for the requested operation.
+    UnsupportedFormat(Format),
+    /// The fourcc format code does not match any `Format`.
+    UnknownFormat(u32),
+    /// The number of planes given does not match the buffer.
+    InvalidPlaneCount { expected: usize, actual: usize },
+    /// A builder was not given a parameter it needs.
+    MissingParameter(&'static str),
+    /// The loaded libgbm does not export the named entry point.
+    Unsupported(&'static str),
+    /// libgbm could not be loaded at runtime.
+    Load(String),
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// An EGL call failed.
+    #[cfg(feature = "egl")]
+    Egl(khronos_egl::Error),
+    /// An X11 request failed.
+    #[cfg(feature = "x11")]
+    X11(ReplyError),
 }
 
 pub type Result<T> = StdResult<T, Error>;
@@ -16,6 +55,35 @@ impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Ioctl(ref err) => err.fmt(fmt),
+            Error::MissingUsage(flags) =>
+                write!(fmt, "buffer was not created with usage {:?}", flags),
+            Error::InvalidLength { expected, actual } =>
+                write!(fmt, "expected {} bytes of data, got {}", expected, actual),
+            Error::InvalidSize(size) =>
+                write!(fmt, "a buffer of {}x{} is too large", size.0, size.1),
+            Error::TooLarge { max, actual } =>
+                write!(fmt, "image of {}x{} does not fit in {}x{}", actual.0, actual.1, max.0, max.1),
+            Error::InvalidHotspot { size, hotspot } =>
+                write!(fmt, "hotspot {},{} is outside the {}x{} cursor", hotspot.0, hotspot.1, size.0, size.1),
+            Error::UnsupportedFlags(flags) =>
+                write!(fmt, "usage {:?} is not supported by libgbm", flags),
+            Error::UnsupportedFormat(format) =>
+                write!(fmt, "format {:?} is not supported", format),
+            Error::UnknownFormat(code) =>
+                write!(fmt, "unknown format {:#010x}", code),
+            Error::InvalidPlaneCount { expected, actual } =>
+                write!(fmt, "expected {} planes, got {}", expected, actual),
+            Error::MissingParameter(name) =>
+                write!(fmt, "no {} was given", name),
+            Error::Unsupported(symbol) =>
+                write!(fmt, "libgbm does not support {}", symbol),
+            Error::Load(ref err) =>
+                write!(fmt, "failed to load libgbm: {}", err),
+            Error::Io(ref err) => err.fmt(fmt),
+            #[cfg(feature = "egl")]
+            Error::Egl(err) => err.fmt(fmt),
+            #[cfg(feature = "x11")]
+            Error::X11(ref err) => err.fmt(fmt),
         }
     }
 }
@@ -23,12 +91,33 @@ impl fmt::Display for Error {
 impl StdError for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::MissingUsage(_) => "missing buffer usage",
+            Error::InvalidLength { .. } => "invalid data length",
+            Error::InvalidSize(_) => "invalid buffer size",
+            Error::TooLarge { .. } => "image too large",
+            Error::InvalidHotspot { .. } => "invalid cursor hotspot",
+            Error::UnsupportedFlags(_) => "unsupported usage flags",
+            Error::UnsupportedFormat(_) => "unsupported format",
+            Error::UnknownFormat(_) => "unknown format",
+            Error::InvalidPlaneCount { .. } => "invalid plane count",
+            Error::MissingParameter(_) => "missing parameter",
+            Error::Unsupported(_) => "unsupported by libgbm",
+            Error::Load(_) => "failed to load libgbm",
+            #[cfg(feature = "egl")]
+            Error::Egl(_) => "EGL call failed",
+            #[cfg(feature = "x11")]
+            Error::X11(_) => "X11 request failed",
             _ => ""
         }
     }
 
     fn cause(&self) -> Option<&StdError> {
         match *self {
+            Error::Io(ref err) => Some(err),
+            #[cfg(feature = "egl")]
+            Error::Egl(ref err) => Some(err),
+            #[cfg(feature = "x11")]
+            Error::X11(ref err) => Some(err),
             _ => None
         }
     }
@@ -39,3 +128,30 @@ impl From<Errno> for Error {
         Error::Ioctl(err)
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "egl")]
+impl From<khronos_egl::Error> for Error {
+    fn from(err: khronos_egl::Error) -> Error {
+        Error::Egl(err)
+    }
+}
+
+#[cfg(feature = "x11")]
+impl From<ReplyError> for Error {
+    fn from(err: ReplyError) -> Error {
+        Error::X11(err)
+    }
+}
+
+#[cfg(feature = "x11")]
+impl From<ConnectionError> for Error {
+    fn from(err: ConnectionError) -> Error {
+        Error::X11(err.into())
+    }
+}
@@ -0,0 +1,270 @@
+//! Format tables and tranches for linux-dmabuf feedback.
+//!
+//! With version 4 of the `zwp_linux_dmabuf_v1` Wayland protocol, the
+//! compositor shares a table of 16-byte entries, a format and a modifier
+//! each, through a sealed memfd. It then sends tranches: groups of indices
+//! into the table for a target device, in order of preference.
+//!
+//! `Feedback` builds the table and tranches on the compositor side, and
+//! `FormatTable::from_fd` reads a received table on the client side. Sending
+//! and receiving the events is left to the Wayland library in use.
+
+use libc;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::ptr::null_mut;
+use std::slice;
+
+use super::{Device, BufferFlags, Format, Modifier};
+use error::{Result, Error};
+
+/// The size of a format table entry.
+pub const ENTRY_SIZE: usize = 16;
+
+/// The number of entries a table can hold, as tranches use 16-bit indices.
+pub const MAX_ENTRIES: usize = 1 << 16;
+
+bitflags! {
+    pub flags TrancheFlags: u32 {
+        /// The buffers can be scanned out on the target device.
+        const TRANCHE_SCANOUT = 1
+    }
+}
+
+/// A table of format and modifier pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct FormatTable {
+    entries: Vec<(u32, Modifier)>
+}
+
+impl FormatTable {
+    /// Creates an empty table.
+    pub fn new() -> FormatTable {
+        FormatTable::default()
+    }
+
+    /// Returns the index of a pair, adding it to the table if needed.
+    pub fn insert(&mut self, format: Format, modifier: Modifier) -> Result<u16> {
+        let entry = (format as u32, modifier);
+        if let Some(index) = self.entries.iter().position(|&e| e == entry) {
+            return Ok(index as u16);
+        }
+        if self.entries.len() == MAX_ENTRIES {
+            return Err(Error::InvalidLength { expected: MAX_ENTRIES, actual: self.entries.len() + 1 });
+        }
+        self.entries.push(entry);
+        Ok((self.entries.len() - 1) as u16)
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entry at an index, or `None` if the index is out of range
+    /// or the format has no `Format` variant.
+    pub fn get(&self, index: u16) -> Option<(Format, Modifier)> {
+        self.entries.get(index as usize).and_then(|&(code, modifier)| {
+            Format::from_fourcc(code).map(|format| (format, modifier))
+        })
+    }
+
+    /// Returns the entries a tranche refers to, skipping unknown ones.
+    pub fn resolve(&self, tranche: &Tranche) -> Vec<(Format, Modifier)> {
+        tranche.formats.iter().filter_map(|&index| self.get(index)).collect()
+    }
+
+    /// Returns the raw fourcc code and modifier of every entry.
+    pub fn entries(&self) -> &[(u32, Modifier)] {
+        &self.entries
+    }
+
+    /// Encodes the table as sent to clients.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.entries.len() * ENTRY_SIZE);
+        for &(code, modifier) in &self.entries {
+            bytes.extend_from_slice(&code.to_ne_bytes());
+            bytes.extend_from_slice(&0u32.to_ne_bytes());
+            bytes.extend_from_slice(&modifier.0.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// Decodes a table. Entries with formats unknown to this crate are kept,
+    /// so indices stay valid.
+    pub fn from_bytes(bytes: &[u8]) -> Result<FormatTable> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) || bytes.len() / ENTRY_SIZE > MAX_ENTRIES {
+            let expected = (bytes.len() / ENTRY_SIZE).min(MAX_ENTRIES) * ENTRY_SIZE;
+            return Err(Error::InvalidLength { expected: expected, actual: bytes.len() });
+        }
+
+        let entries = bytes.chunks(ENTRY_SIZE).map(|entry| {
+            let mut code = [0; 4];
+            let mut modifier = [0; 8];
+            code.copy_from_slice(&entry[0..4]);
+            modifier.copy_from_slice(&entry[8..16]);
+            (u32::from_ne_bytes(code), Modifier(u64::from_ne_bytes(modifier)))
+        }).collect();
+        Ok(FormatTable { entries: entries })
+    }
+
+    /// Writes the table to a sealed memfd, returning it with its size for
+    /// the `format_table` event.
+    pub fn to_memfd(&self) -> Result<(OwnedFd, u32)> {
+        let bytes = self.to_bytes();
+        let mut file = unsafe {
+            let fd = libc::memfd_create(b"gbm-format-table\0".as_ptr() as *const _,
+                                        libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING);
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            File::from_raw_fd(fd)
+        };
+        try!(file.write_all(&bytes));
+
+        let seals = libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok((file.into(), bytes.len() as u32))
+    }
+
+    /// Reads a table received in a `format_table` event.
+    pub fn from_fd(fd: BorrowedFd, size: u32) -> Result<FormatTable> {
+        if size == 0 {
+            return Ok(FormatTable::new());
+        }
+
+        let len = size as usize;
+        let data = unsafe {
+            libc::mmap(null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, fd.as_raw_fd(), 0)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+        let table = FormatTable::from_bytes(unsafe { slice::from_raw_parts(data as *const u8, len) });
+        unsafe { libc::munmap(data, len) };
+        table
+    }
+}
+
+/// A group of table entries usable on a target device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tranche {
+    /// The device number of the target device.
+    pub device: u64,
+    /// How buffers of the tranche are used on the target device.
+    pub flags: TrancheFlags,
+    /// Indices into the format table.
+    pub formats: Vec<u16>
+}
+
+impl Tranche {
+    /// Encodes the indices as sent in the `tranche_formats` event.
+    pub fn formats_to_bytes(&self) -> Vec<u8> {
+        self.formats.iter().flat_map(|index| index.to_ne_bytes()).collect()
+    }
+
+    /// Decodes the indices received in a `tranche_formats` event.
+    pub fn formats_from_bytes(bytes: &[u8]) -> Result<Vec<u16>> {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(Error::InvalidLength { expected: bytes.len() - 1, actual: bytes.len() });
+        }
+        Ok(bytes.chunks(2).map(|index| u16::from_ne_bytes([index[0], index[1]])).collect())
+    }
+}
+
+/// The feedback a compositor sends for a surface or as its default.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Feedback {
+    /// The device number of the device the compositor renders with.
+    pub main_device: u64,
+    /// The entries the tranches refer to.
+    pub table: FormatTable,
+    /// The tranches, most preferred first.
+    pub tranches: Vec<Tranche>
+}
+
+impl Feedback {
+    /// Creates feedback without tranches for a main device.
+    pub fn new(main_device: u64) -> Feedback {
+        Feedback {
+            main_device: main_device,
+            table: FormatTable::new(),
+            tranches: Vec::new()
+        }
+    }
+
+    /// Adds a tranche of format and modifier pairs, after the ones already
+    /// added.
+    pub fn add_tranche<I>(&mut self, device: u64, flags: TrancheFlags, formats: I) -> Result<()>
+        where I: IntoIterator<Item = (Format, Modifier)>
+    {
+        let mut indices = Vec::new();
+        for (format, modifier) in formats {
+            let index = try!(self.table.insert(format, modifier));
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+
+        self.tranches.push(Tranche { device: device, flags: flags, formats: indices });
+        Ok(())
+    }
+
+    /// Adds a tranche for the formats a `Device` supports with the given
+    /// usage, targeting the device itself.
+    ///
+    /// libgbm cannot list modifiers, so each format is paired with the
+    /// candidate modifiers the device supports, and with `Modifier::INVALID`
+    /// for buffers allocated without one.
+    pub fn add_device_tranche<F>(&mut self, device: &Device<F>, flags: TrancheFlags, usage: BufferFlags, modifiers: &[Modifier]) -> Result<()>
+        where F: AsRef<File>
+    {
+        let target = try!(device_number(device.as_ref()));
+        let mut formats = Vec::new();
+        for &format in Format::all() {
+            if !device.is_format_supported(format, usage) {
+                continue;
+            }
+            for &modifier in modifiers {
+                if modifier != Modifier::INVALID && device.is_modifier_supported(format, modifier).unwrap_or(false) {
+                    formats.push((format, modifier));
+                }
+            }
+            formats.push((format, Modifier::INVALID));
+        }
+        self.add_tranche(target, flags, formats)
+    }
+}
+
+/// Returns the device number of an opened DRM device file, as sent in the
+/// `main_device` and `tranche_target_device` events.
+pub fn device_number(file: &File) -> Result<u64> {
+    Ok(try!(file.metadata()).rdev())
+}
+
+/// Encodes a device number as sent in the `main_device` and
+/// `tranche_target_device` events.
+pub fn device_to_bytes(device: u64) -> Vec<u8> {
+    device.to_ne_bytes().to_vec()
+}
+
+/// Decodes a device number received in the `main_device` and
+/// `tranche_target_device` events.
+pub fn device_from_bytes(bytes: &[u8]) -> Result<u64> {
+    let mut device = [0; 8];
+    if bytes.len() != device.len() {
+        return Err(Error::InvalidLength { expected: device.len(), actual: bytes.len() });
+    }
+    device.copy_from_slice(bytes);
+    Ok(u64::from_ne_bytes(device))
+}
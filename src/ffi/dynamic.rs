@@ -0,0 +1,120 @@
+//! Loads libgbm at runtime instead of linking it.
+//!
+//! The generated bindings are still used for types, but every required
+//! function is shadowed by one that calls through a table resolved with
+//! `dlopen` the first time a device is created.
+
+#![allow(dead_code, non_camel_case_types)]
+
+use libc;
+use super::super::error::{Result, Error};
+use super::gbm_shim::*;
+
+use std::ffi::CStr;
+use std::mem::transmute;
+use std::os::raw::{c_char, c_int, c_void};
+use std::sync::OnceLock;
+
+/// The file name libgbm is loaded from.
+const LIBRARY_NAME: &'static [u8] = b"libgbm.so.1\0";
+
+static LIBRARY: OnceLock<::std::result::Result<Library, String>> = OnceLock::new();
+
+/// Returns the last `dlerror` message.
+unsafe fn last_error() -> String {
+    let err = libc::dlerror();
+    if err.is_null() {
+        return "unknown error".to_owned();
+    }
+    CStr::from_ptr(err).to_string_lossy().into_owned()
+}
+
+macro_rules! library {
+    ( $( fn $name:ident ( $( $arg:ident : $ty:ty ),* ) -> $ret:ty; )* ) => {
+        struct Library {
+            /// The `dlopen` handle, stored as an integer to keep the table `Sync`.
+            handle: usize,
+            $( $name: unsafe extern "C" fn($( $ty ),*) -> $ret, )*
+        }
+
+        impl Library {
+            unsafe fn open() -> ::std::result::Result<Library, String> {
+                let handle = libc::dlopen(LIBRARY_NAME.as_ptr() as *const _, libc::RTLD_NOW | libc::RTLD_LOCAL);
+                if handle.is_null() {
+                    return Err(last_error());
+                }
+
+                $(
+                    let $name = libc::dlsym(handle, concat!(stringify!($name), "\0").as_ptr() as *const _);
+                    if $name.is_null() {
+                        let err = last_error();
+                        libc::dlclose(handle);
+                        return Err(err);
+                    }
+                )*
+
+                Ok(Library {
+                    handle: handle as usize,
+                    $( $name: transmute::<*mut c_void, unsafe extern "C" fn($( $ty ),*) -> $ret>($name), )*
+                })
+            }
+        }
+
+        $(
+            pub unsafe fn $name($( $arg: $ty ),*) -> $ret {
+                (library().$name)($( $arg ),*)
+            }
+        )*
+    }
+}
+
+library! {
+    fn gbm_device_get_fd(gbm: *mut gbm_device) -> c_int;
+    fn gbm_device_get_backend_name(gbm: *mut gbm_device) -> *const c_char;
+    fn gbm_device_is_format_supported(gbm: *mut gbm_device, format: u32, usage: u32) -> c_int;
+    fn gbm_device_destroy(gbm: *mut gbm_device) -> ();
+    fn gbm_create_device(fd: c_int) -> *mut gbm_device;
+    fn gbm_bo_create(gbm: *mut gbm_device, width: u32, height: u32, format: u32, flags: u32) -> *mut gbm_bo;
+    fn gbm_bo_import(gbm: *mut gbm_device, type_: u32, buffer: *mut c_void, usage: u32) -> *mut gbm_bo;
+    fn gbm_bo_map(bo: *mut gbm_bo, x: u32, y: u32, width: u32, height: u32, flags: u32, stride: *mut u32, map_data: *mut *mut c_void) -> *mut c_void;
+    fn gbm_bo_unmap(bo: *mut gbm_bo, map_data: *mut c_void) -> ();
+    fn gbm_bo_get_width(bo: *mut gbm_bo) -> u32;
+    fn gbm_bo_get_height(bo: *mut gbm_bo) -> u32;
+    fn gbm_bo_get_stride(bo: *mut gbm_bo) -> u32;
+    fn gbm_bo_get_format(bo: *mut gbm_bo) -> u32;
+    fn gbm_bo_get_device(bo: *mut gbm_bo) -> *mut gbm_device;
+    fn gbm_bo_get_handle(bo: *mut gbm_bo) -> gbm_bo_handle;
+    fn gbm_bo_get_fd(bo: *mut gbm_bo) -> c_int;
+    fn gbm_bo_write(bo: *mut gbm_bo, buf: *const c_void, count: size_t) -> c_int;
+    fn gbm_bo_set_user_data(bo: *mut gbm_bo, data: *mut c_void, destroy_user_data: Option<unsafe extern "C" fn(*mut gbm_bo, *mut c_void)>) -> ();
+    fn gbm_bo_get_user_data(bo: *mut gbm_bo) -> *mut c_void;
+    fn gbm_bo_destroy(bo: *mut gbm_bo) -> ();
+    fn gbm_surface_create(gbm: *mut gbm_device, width: u32, height: u32, format: u32, flags: u32) -> *mut gbm_surface;
+    fn gbm_surface_needs_lock_front_buffer(surface: *mut gbm_surface) -> c_int;
+    fn gbm_surface_lock_front_buffer(surface: *mut gbm_surface) -> *mut gbm_bo;
+    fn gbm_surface_release_buffer(surface: *mut gbm_surface, bo: *mut gbm_bo) -> ();
+    fn gbm_surface_has_free_buffers(surface: *mut gbm_surface) -> c_int;
+    fn gbm_surface_destroy(surface: *mut gbm_surface) -> ();
+}
+
+/// Loads libgbm if it has not been loaded yet.
+pub fn load() -> Result<()> {
+    match *LIBRARY.get_or_init(|| unsafe { Library::open() }) {
+        Ok(_) => Ok(()),
+        Err(ref err) => Err(Error::Load(err.clone()))
+    }
+}
+
+/// Returns the `dlopen` handle of the loaded libgbm.
+pub fn handle() -> *mut c_void {
+    library().handle as *mut _
+}
+
+/// Every gbm object comes from a device, and creating a device loads the
+/// library first, so the table is always available here.
+fn library() -> &'static Library {
+    match LIBRARY.get() {
+        Some(&Ok(ref library)) => library,
+        _ => panic!("libgbm used before it was loaded")
+    }
+}
@@ -193,7 +193,7 @@ pub enum gbm_bo_transfer_flags {
     GBM_BO_TRANSFER_WRITE = 2,
     GBM_BO_TRANSFER_READ_WRITE = 3,
 }
-#[link(name = "gbm", kind = "dylib")]
+#[cfg_attr(not(feature = "dlopen"), link(name = "gbm", kind = "dylib"))]
 extern "C" {
     pub fn gbm_device_get_fd(gbm: *mut gbm_device) -> ::std::os::raw::c_int;
     pub fn gbm_device_get_backend_name(gbm: *mut gbm_device)
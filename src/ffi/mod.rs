@@ -1,12 +1,29 @@
 mod gbm_shim;
+mod optional;
+#[cfg(feature = "dlopen")]
+mod dynamic;
 
 use errno::{Errno, errno, set_errno};
+use super::Format;
 use super::error::{Result, Error};
 
 pub use self::gbm_shim::*;
+pub use self::optional::*;
+#[cfg(feature = "dlopen")]
+#[allow(unused_imports)]
+pub use self::dynamic::{
+    gbm_device_get_fd, gbm_device_get_backend_name, gbm_device_is_format_supported,
+    gbm_device_destroy, gbm_create_device, gbm_bo_create, gbm_bo_import, gbm_bo_map,
+    gbm_bo_unmap, gbm_bo_get_width, gbm_bo_get_height, gbm_bo_get_stride,
+    gbm_bo_get_format, gbm_bo_get_device, gbm_bo_get_handle, gbm_bo_get_fd, gbm_bo_write,
+    gbm_bo_set_user_data, gbm_bo_get_user_data, gbm_bo_destroy, gbm_surface_create,
+    gbm_surface_needs_lock_front_buffer, gbm_surface_lock_front_buffer,
+    gbm_surface_release_buffer, gbm_surface_has_free_buffers, gbm_surface_destroy
+};
+use std::cell::Cell;
 use std::os::unix::io::RawFd;
 use std::mem::transmute;
-use std::os::raw::c_void;
+use std::os::raw::{c_int, c_uint, c_void};
 use std::ptr::null_mut;
 use std::rc::Rc;
 
@@ -22,22 +39,109 @@ macro_rules! gbm_cmd {
     })
 }
 
+/// Usage flags added after the generated bindings.
+pub const GBM_BO_USE_PROTECTED: u32 = 1 << 5;
+pub const GBM_BO_USE_FRONT_RENDERING: u32 = 1 << 6;
+
+/// The usage flags every libgbm understands.
+pub const BASE_FLAGS: u32 = gbm_bo_flags::GBM_BO_USE_SCANOUT as u32 |
+                            gbm_bo_flags::GBM_BO_USE_CURSOR as u32 |
+                            gbm_bo_flags::GBM_BO_USE_RENDERING as u32 |
+                            gbm_bo_flags::GBM_BO_USE_WRITE as u32 |
+                            gbm_bo_flags::GBM_BO_USE_LINEAR as u32;
+
+/// The usage flags known to this crate that not every libgbm understands.
+pub const NEWER_FLAGS: u32 = GBM_BO_USE_PROTECTED | GBM_BO_USE_FRONT_RENDERING;
+
+/// Returns the flags in `flags` that `supports` rejects.
+///
+/// Every bit outside `BASE_FLAGS` is passed to `supports` on its own, so
+/// flags that later libgbm releases add are checked the same way.
+pub fn unsupported_flags<S>(flags: u32, mut supports: S) -> u32 where S: FnMut(u32) -> bool {
+    (0..32).map(|bit| 1u32 << bit)
+           .filter(|&flag| flags & flag != 0 && BASE_FLAGS & flag == 0 && !supports(flag))
+           .fold(0, |unsupported, flag| unsupported | flag)
+}
+
+/// Returns the name libgbm gives to a fourcc format code.
+pub fn format_name(format: u32) -> Result<String> {
+    let func = try!(optional().gbm_format_get_name.ok_or(Error::Unsupported("gbm_format_get_name")));
+    let mut desc = gbm_format_name_desc::default();
+    let ptr = unsafe { func(format, &mut desc) };
+    if ptr.is_null() {
+        return Err(Error::Unsupported("gbm_format_get_name"));
+    }
+    let name = desc.name[..4].iter().map(|&c| c as u8 as char).collect();
+    Ok(name)
+}
+
 #[derive(Debug)]
 pub struct GbmDevice {
-    pub raw: *mut gbm_device
+    pub raw: *mut gbm_device,
+    /// The flags outside `BASE_FLAGS` that were probed, and those of them
+    /// that allocations succeeded with.
+    probed: Cell<u32>,
+    supported: Cell<u32>
 }
 
 impl GbmDevice {
     pub fn new(fd: RawFd) -> Result<GbmDevice> {
+        #[cfg(feature = "dlopen")]
+        try!(dynamic::load());
+
         let ptr = gbm_cmd!(gbm_create_device(fd));
         let dev = GbmDevice {
-            raw: ptr
+            raw: ptr,
+            probed: Cell::new(0),
+            supported: Cell::new(0)
         };
 
         Ok(dev)
     }
 }
 
+impl GbmDevice {
+    pub fn is_format_supported(&self, format: u32, flags: u32) -> bool {
+        unsafe { gbm_device_is_format_supported(self.raw, format, flags) != 0 }
+    }
+
+    /// Returns true if buffers can be allocated with a single usage flag.
+    ///
+    /// libgbm has no query for this and no version to check, so a small
+    /// buffer is allocated with the flag and the result cached. A libgbm
+    /// older than the flag passes it on to the driver, which may ignore it
+    /// and report it as supported.
+    pub fn supports_flag(&self, flag: u32) -> bool {
+        if BASE_FLAGS & flag != 0 {
+            return true;
+        }
+        if self.probed.get() & flag == 0 {
+            let bo = unsafe { gbm_bo_create(self.raw, 64, 64, Format::XRGB8888 as u32, flag) };
+            if !bo.is_null() {
+                unsafe { gbm_bo_destroy(bo) };
+                self.supported.set(self.supported.get() | flag);
+            }
+            self.probed.set(self.probed.get() | flag);
+        }
+        self.supported.get() & flag != 0
+    }
+
+    /// Returns the usage flags known to this crate that the device
+    /// supports, probing them with `supports_flag`.
+    pub fn supported_flags(&self) -> u32 {
+        BASE_FLAGS | (NEWER_FLAGS & !unsupported_flags(NEWER_FLAGS, |flag| self.supports_flag(flag)))
+    }
+
+    /// Returns the number of planes the device uses for a format and
+    /// modifier, or `None` if it does not support the combination.
+    pub fn format_modifier_plane_count(&self, format: u32, modifier: u64) -> Result<Option<u32>> {
+        let func = try!(optional().gbm_device_get_format_modifier_plane_count
+                        .ok_or(Error::Unsupported("gbm_device_get_format_modifier_plane_count")));
+        let count = unsafe { func(self.raw, format, modifier) };
+        Ok(if count > 0 { Some(count as u32) } else { None })
+    }
+}
+
 impl Drop for GbmDevice {
     fn drop(&mut self) {
         unsafe { gbm_device_destroy(self.raw) };
@@ -60,6 +164,24 @@ impl GbmSurface {
         Ok(surface)
     }
 
+    /// Creates a surface with one of the given modifiers, passing the usage
+    /// flags when libgbm is new enough to take them.
+    pub fn with_modifiers(device: &GbmDevice, width: u32, height: u32, format: u32, modifiers: &[u64], flags: u32) -> Result<GbmSurface> {
+        let optional = optional();
+        let ptr = if let Some(func) = optional.gbm_surface_create_with_modifiers2 {
+            gbm_cmd!(func(device.raw, width, height, format, modifiers.as_ptr(), modifiers.len() as c_uint, flags))
+        } else {
+            let func = try!(optional.gbm_surface_create_with_modifiers
+                            .ok_or(Error::Unsupported("gbm_surface_create_with_modifiers")));
+            gbm_cmd!(func(device.raw, width, height, format, modifiers.as_ptr(), modifiers.len() as c_uint))
+        };
+        let surface = GbmSurface {
+            raw: ptr
+        };
+
+        Ok(surface)
+    }
+
     pub fn lock_front_buffer(&self) -> Result<GbmBufferObject> {
         let ptr = gbm_cmd!(gbm_surface_lock_front_buffer(self.raw));
         let buffer = GbmBufferObject {
@@ -96,6 +218,50 @@ impl GbmBufferObject {
         Ok(buffer)
     }
 
+    /// Creates a buffer with one of the given modifiers, passing the usage
+    /// flags when libgbm is new enough to take them.
+    pub fn with_modifiers(device: &GbmDevice, width: u32, height: u32, format: u32, modifiers: &[u64], flags: u32) -> Result<GbmBufferObject> {
+        let optional = optional();
+        let ptr = if let Some(func) = optional.gbm_bo_create_with_modifiers2 {
+            gbm_cmd!(func(device.raw, width, height, format, modifiers.as_ptr(), modifiers.len() as c_uint, flags))
+        } else {
+            let func = try!(optional.gbm_bo_create_with_modifiers
+                            .ok_or(Error::Unsupported("gbm_bo_create_with_modifiers")));
+            gbm_cmd!(func(device.raw, width, height, format, modifiers.as_ptr(), modifiers.len() as c_uint))
+        };
+        let buffer = GbmBufferObject {
+            raw: ptr
+        };
+
+        Ok(buffer)
+    }
+
+    pub fn import_fd(device: &GbmDevice, fd: RawFd, width: u32, height: u32, stride: u32, format: u32, flags: u32) -> Result<GbmBufferObject> {
+        let mut data = gbm_import_fd_data {
+            fd: fd,
+            width: width,
+            height: height,
+            stride: stride,
+            format: format
+        };
+        let ptr = gbm_cmd!(gbm_bo_import(device.raw, GBM_BO_IMPORT_FD as u32, &mut data as *mut _ as *mut c_void, flags));
+        let buffer = GbmBufferObject {
+            raw: ptr
+        };
+
+        Ok(buffer)
+    }
+
+    pub fn import_fd_modifier(device: &GbmDevice, data: &gbm_import_fd_modifier_data, flags: u32) -> Result<GbmBufferObject> {
+        let mut data = *data;
+        let ptr = gbm_cmd!(gbm_bo_import(device.raw, GBM_BO_IMPORT_FD_MODIFIER, &mut data as *mut _ as *mut c_void, flags));
+        let buffer = GbmBufferObject {
+            raw: ptr
+        };
+
+        Ok(buffer)
+    }
+
     pub fn width(&self) -> u32 {
         unsafe { gbm_bo_get_width(self.raw) }
     }
@@ -116,6 +282,85 @@ impl GbmBufferObject {
         unsafe { transmute(gbm_bo_get_handle(self.raw)) }
     }
 
+    #[cfg(feature = "drm")]
+    pub fn handle_u32(&self) -> u32 {
+        let mut handle = unsafe { gbm_bo_get_handle(self.raw) };
+        unsafe { *handle.u32_() }
+    }
+
+    pub fn fd(&self) -> Result<RawFd> {
+        set_errno(Errno(0));
+        let fd = unsafe { gbm_bo_get_fd(self.raw) };
+        if fd < 0 {
+            return Err(Error::Ioctl(errno()));
+        }
+        Ok(fd)
+    }
+
+    pub fn fd_for_plane(&self, plane: u32) -> Result<RawFd> {
+        let func = try!(optional().gbm_bo_get_fd_for_plane.ok_or(Error::Unsupported("gbm_bo_get_fd_for_plane")));
+        set_errno(Errno(0));
+        let fd = unsafe { func(self.raw, plane as c_int) };
+        if fd < 0 {
+            return Err(Error::Ioctl(errno()));
+        }
+        Ok(fd)
+    }
+
+    /// Maps a region of the buffer, returning the pointer to its first
+    /// pixel, its stride and the data needed to unmap it.
+    pub fn map(&self, x: u32, y: u32, width: u32, height: u32, flags: u32) -> Result<(*mut u8, u32, *mut c_void)> {
+        let mut stride = 0;
+        let mut map_data = null_mut();
+        let ptr = gbm_cmd!(gbm_bo_map(self.raw, x, y, width, height, flags, &mut stride, &mut map_data));
+        Ok((ptr as *mut u8, stride, map_data))
+    }
+
+    pub fn unmap(&self, map_data: *mut c_void) {
+        unsafe { gbm_bo_unmap(self.raw, map_data) };
+    }
+
+    pub fn modifier(&self) -> Result<u64> {
+        let func = try!(optional().gbm_bo_get_modifier.ok_or(Error::Unsupported("gbm_bo_get_modifier")));
+        Ok(unsafe { func(self.raw) })
+    }
+
+    pub fn plane_count(&self) -> Result<u32> {
+        let func = try!(optional().gbm_bo_get_plane_count.ok_or(Error::Unsupported("gbm_bo_get_plane_count")));
+        Ok(unsafe { func(self.raw) } as u32)
+    }
+
+    pub fn stride_for_plane(&self, plane: u32) -> Result<u32> {
+        let func = try!(optional().gbm_bo_get_stride_for_plane.ok_or(Error::Unsupported("gbm_bo_get_stride_for_plane")));
+        Ok(unsafe { func(self.raw, plane as c_int) })
+    }
+
+    pub fn offset(&self, plane: u32) -> Result<u32> {
+        let func = try!(optional().gbm_bo_get_offset.ok_or(Error::Unsupported("gbm_bo_get_offset")));
+        Ok(unsafe { func(self.raw, plane as c_int) })
+    }
+
+    pub fn handle_for_plane(&self, plane: u32) -> Result<u32> {
+        let func = try!(optional().gbm_bo_get_handle_for_plane.ok_or(Error::Unsupported("gbm_bo_get_handle_for_plane")));
+        let mut handle = unsafe { func(self.raw, plane as c_int) };
+        Ok(unsafe { *handle.u32_() })
+    }
+
+    pub fn bpp(&self) -> Result<u32> {
+        let func = try!(optional().gbm_bo_get_bpp.ok_or(Error::Unsupported("gbm_bo_get_bpp")));
+        Ok(unsafe { func(self.raw) })
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        unsafe {
+            set_errno(Errno(0));
+            if gbm_bo_write(self.raw, data.as_ptr() as *const _, data.len()) != 0 {
+                return Err(Error::Ioctl(errno()));
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_user_data<T: Sized>(&self, data: Option<Rc<T>>) {
         unsafe {
             // If we have user data already, destroy it.
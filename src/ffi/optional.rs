@@ -0,0 +1,119 @@
+//! Entry points and types added to libgbm after the generated bindings.
+//!
+//! These are resolved at runtime so that binaries still start against an
+//! older libgbm, and callers can check whether they are available.
+
+#![allow(dead_code, non_camel_case_types)]
+
+use libc;
+use super::gbm_shim::*;
+
+use std::mem::transmute;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::sync::OnceLock;
+
+pub const GBM_MAX_PLANES: usize = 4;
+pub const GBM_BO_IMPORT_FD_MODIFIER: u32 = 0x5504;
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct gbm_import_fd_modifier_data {
+    pub width: u32,
+    pub height: u32,
+    pub format: u32,
+    pub num_fds: u32,
+    pub fds: [c_int; GBM_MAX_PLANES],
+    pub strides: [c_int; GBM_MAX_PLANES],
+    pub offsets: [c_int; GBM_MAX_PLANES],
+    pub modifier: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct gbm_format_name_desc {
+    pub name: [c_char; 5],
+}
+
+static OPTIONAL: OnceLock<Optional> = OnceLock::new();
+
+/// Looks up a nul-terminated symbol in the loaded libgbm.
+#[cfg(not(feature = "dlopen"))]
+unsafe fn lookup(name: &[u8]) -> *mut c_void {
+    libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const _)
+}
+
+/// Looks up a nul-terminated symbol in the loaded libgbm.
+#[cfg(feature = "dlopen")]
+unsafe fn lookup(name: &[u8]) -> *mut c_void {
+    libc::dlsym(super::dynamic::handle(), name.as_ptr() as *const _)
+}
+
+macro_rules! optional {
+    ( $( fn $name:ident ( $( $ty:ty ),* ) -> $ret:ty; )* ) => {
+        /// The optional entry points of libgbm, `None` when not exported.
+        pub struct Optional {
+            $( pub $name: Option<unsafe extern "C" fn($( $ty ),*) -> $ret>, )*
+        }
+
+        impl Optional {
+            /// Resolves every entry point with `lookup`, which is given the
+            /// nul-terminated symbol name and returns null if it is missing.
+            pub unsafe fn resolve<L>(lookup: L) -> Optional where L: Fn(&[u8]) -> *mut c_void {
+                Optional {
+                    $( $name: transmute::<*mut c_void, Option<unsafe extern "C" fn($( $ty ),*) -> $ret>>(
+                        lookup(concat!(stringify!($name), "\0").as_bytes())), )*
+                }
+            }
+        }
+    }
+}
+
+optional! {
+    fn gbm_bo_get_modifier(*mut gbm_bo) -> u64;
+    fn gbm_bo_get_plane_count(*mut gbm_bo) -> c_int;
+    fn gbm_bo_get_stride_for_plane(*mut gbm_bo, c_int) -> u32;
+    fn gbm_bo_get_offset(*mut gbm_bo, c_int) -> u32;
+    fn gbm_bo_get_handle_for_plane(*mut gbm_bo, c_int) -> gbm_bo_handle;
+    fn gbm_bo_get_fd_for_plane(*mut gbm_bo, c_int) -> c_int;
+    fn gbm_bo_get_bpp(*mut gbm_bo) -> u32;
+    fn gbm_format_get_name(u32, *mut gbm_format_name_desc) -> *mut c_char;
+    fn gbm_bo_create_with_modifiers(*mut gbm_device, u32, u32, u32, *const u64, c_uint) -> *mut gbm_bo;
+    fn gbm_bo_create_with_modifiers2(*mut gbm_device, u32, u32, u32, *const u64, c_uint, u32) -> *mut gbm_bo;
+    fn gbm_surface_create_with_modifiers(*mut gbm_device, u32, u32, u32, *const u64, c_uint) -> *mut gbm_surface;
+    fn gbm_surface_create_with_modifiers2(*mut gbm_device, u32, u32, u32, *const u64, c_uint, u32) -> *mut gbm_surface;
+    fn gbm_device_get_format_modifier_plane_count(*mut gbm_device, u32, u64) -> c_int;
+}
+
+/// Returns the optional entry points of the loaded libgbm.
+pub fn optional() -> &'static Optional {
+    OPTIONAL.get_or_init(|| unsafe { Optional::resolve(|name| lookup(name)) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Optional;
+    use super::super::gbm_shim::gbm_bo;
+
+    use std::os::raw::c_void;
+    use std::ptr::null_mut;
+
+    unsafe extern "C" fn get_bpp(_: *mut gbm_bo) -> u32 {
+        32
+    }
+
+    #[test]
+    fn missing_symbols() {
+        let optional = unsafe {
+            Optional::resolve(|name| {
+                if name == b"gbm_bo_get_bpp\0" { get_bpp as *mut c_void } else { null_mut() }
+            })
+        };
+        assert!(optional.gbm_bo_get_modifier.is_none());
+        assert!(optional.gbm_bo_create_with_modifiers2.is_none());
+        assert!(optional.gbm_format_get_name.is_none());
+
+        let func = optional.gbm_bo_get_bpp.unwrap();
+        assert_eq!(unsafe { func(null_mut()) }, 32);
+    }
+}
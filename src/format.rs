@@ -0,0 +1,181 @@
+use std::fmt;
+
+/// Builds a DRM fourcc format code from its four characters.
+pub const fn fourcc(a: u8, b: u8, c: u8, d: u8) -> u32 {
+    (a as u32) | (b as u32) << 8 | (c as u32) << 16 | (d as u32) << 24
+}
+
+/// A pixel format, using the DRM fourcc codes understood by libgbm and KMS.
+///
+/// This replaces the earlier `Format`, which only had `XRGB8888` and
+/// `ARGB8888` with the values of libgbm's `GBM_BO_FORMAT_XRGB8888` (0) and
+/// `GBM_BO_FORMAT_ARGB8888` (1). libgbm creates the same buffers for either
+/// code, but `Format::XRGB8888 as u32` is now the fourcc, which is what
+/// `Buffer::format` returns, and matches on `Format` need a wildcard arm.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Format {
+    C8 = fourcc(b'C', b'8', b' ', b' '),
+    R8 = fourcc(b'R', b'8', b' ', b' '),
+    GR88 = fourcc(b'G', b'R', b'8', b'8'),
+    RGB565 = fourcc(b'R', b'G', b'1', b'6'),
+    BGR565 = fourcc(b'B', b'G', b'1', b'6'),
+    RGB888 = fourcc(b'R', b'G', b'2', b'4'),
+    BGR888 = fourcc(b'B', b'G', b'2', b'4'),
+    XRGB8888 = fourcc(b'X', b'R', b'2', b'4'),
+    XBGR8888 = fourcc(b'X', b'B', b'2', b'4'),
+    RGBX8888 = fourcc(b'R', b'X', b'2', b'4'),
+    BGRX8888 = fourcc(b'B', b'X', b'2', b'4'),
+    ARGB8888 = fourcc(b'A', b'R', b'2', b'4'),
+    ABGR8888 = fourcc(b'A', b'B', b'2', b'4'),
+    RGBA8888 = fourcc(b'R', b'A', b'2', b'4'),
+    BGRA8888 = fourcc(b'B', b'A', b'2', b'4'),
+    XRGB2101010 = fourcc(b'X', b'R', b'3', b'0'),
+    XBGR2101010 = fourcc(b'X', b'B', b'3', b'0'),
+    ARGB2101010 = fourcc(b'A', b'R', b'3', b'0'),
+    ABGR2101010 = fourcc(b'A', b'B', b'3', b'0'),
+    XBGR16161616F = fourcc(b'X', b'B', b'4', b'H'),
+    ABGR16161616F = fourcc(b'A', b'B', b'4', b'H'),
+    YUYV = fourcc(b'Y', b'U', b'Y', b'V'),
+    UYVY = fourcc(b'U', b'Y', b'V', b'Y'),
+    NV12 = fourcc(b'N', b'V', b'1', b'2'),
+    NV21 = fourcc(b'N', b'V', b'2', b'1'),
+    P010 = fourcc(b'P', b'0', b'1', b'0'),
+    YUV420 = fourcc(b'Y', b'U', b'1', b'2'),
+    YVU420 = fourcc(b'Y', b'V', b'1', b'2')
+}
+
+/// Every `Format`, in declaration order.
+pub const FORMATS: &'static [Format] = &[
+    Format::C8, Format::R8, Format::GR88, Format::RGB565, Format::BGR565,
+    Format::RGB888, Format::BGR888, Format::XRGB8888, Format::XBGR8888,
+    Format::RGBX8888, Format::BGRX8888, Format::ARGB8888, Format::ABGR8888,
+    Format::RGBA8888, Format::BGRA8888, Format::XRGB2101010, Format::XBGR2101010,
+    Format::ARGB2101010, Format::ABGR2101010, Format::XBGR16161616F,
+    Format::ABGR16161616F, Format::YUYV, Format::UYVY, Format::NV12, Format::NV21,
+    Format::P010, Format::YUV420, Format::YVU420
+];
+
+impl Format {
+    /// Returns the `Format` matching a fourcc code, if it is known.
+    pub fn from_fourcc(code: u32) -> Option<Format> {
+        FORMATS.iter().cloned().find(|&format| format as u32 == code)
+    }
+
+    /// Returns every known `Format`.
+    pub fn all() -> &'static [Format] {
+        FORMATS
+    }
+
+    /// Returns the number of bytes used by each pixel, or `None` for formats
+    /// with several planes.
+    pub fn bytes_per_pixel(&self) -> Option<u32> {
+        match *self {
+            Format::C8 | Format::R8 => Some(1),
+            Format::GR88 | Format::RGB565 | Format::BGR565 |
+            Format::YUYV | Format::UYVY => Some(2),
+            Format::RGB888 | Format::BGR888 => Some(3),
+            Format::XRGB8888 | Format::XBGR8888 | Format::RGBX8888 |
+            Format::BGRX8888 | Format::ARGB8888 | Format::ABGR8888 |
+            Format::RGBA8888 | Format::BGRA8888 | Format::XRGB2101010 |
+            Format::XBGR2101010 | Format::ARGB2101010 | Format::ABGR2101010 => Some(4),
+            Format::XBGR16161616F | Format::ABGR16161616F => Some(8),
+            Format::NV12 | Format::NV21 | Format::P010 |
+            Format::YUV420 | Format::YVU420 => None
+        }
+    }
+
+    /// Returns true if the format has an alpha channel.
+    pub fn has_alpha(&self) -> bool {
+        matches!(*self, Format::ARGB8888 | Format::ABGR8888 | Format::RGBA8888 | Format::BGRA8888 |
+                        Format::ARGB2101010 | Format::ABGR2101010 | Format::ABGR16161616F)
+    }
+}
+
+/// A format modifier, describing the tiling and compression of a buffer's
+/// memory layout.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Modifier(pub u64);
+
+impl Modifier {
+    /// The buffer is laid out row by row without tiling or compression.
+    pub const LINEAR: Modifier = Modifier(0);
+
+    /// The modifier is unknown or implicit and chosen by the driver.
+    pub const INVALID: Modifier = Modifier(0x00ff_ffff_ffff_ffff);
+
+    /// Returns the vendor code stored in the top byte of the modifier.
+    pub fn vendor(&self) -> u8 {
+        (self.0 >> 56) as u8
+    }
+
+    /// Returns the kind of memory layout the modifier describes.
+    ///
+    /// Compression is recognized for the vendors whose modifiers encode it:
+    /// Intel CCS, AMD DCC, NVIDIA, ARM AFBC and AFRC, Qualcomm and Amlogic.
+    /// Any other modifier is assumed to be tiled.
+    pub fn layout(&self) -> Layout {
+        match *self {
+            Modifier::LINEAR => return Layout::Linear,
+            Modifier::INVALID => return Layout::Implicit,
+            _ => ()
+        }
+
+        let value = self.0 & 0x00ff_ffff_ffff_ffff;
+        let compressed = match self.vendor() {
+            VENDOR_INTEL => matches!(value, 4..=8 | 10..=17),
+            VENDOR_AMD => value & (1 << 13) != 0,
+            VENDOR_NVIDIA => value & 0x10 != 0 && (value >> 23) & 0x7 != 0,
+            VENDOR_QCOM => value == 1,
+            VENDOR_ARM => matches!(value >> 52, 0 | 2),
+            VENDOR_AMLOGIC => true,
+            _ => false
+        };
+        if compressed { Layout::Compressed } else { Layout::Tiled }
+    }
+}
+
+const VENDOR_INTEL: u8 = 0x01;
+const VENDOR_AMD: u8 = 0x02;
+const VENDOR_NVIDIA: u8 = 0x03;
+const VENDOR_QCOM: u8 = 0x05;
+const VENDOR_ARM: u8 = 0x08;
+const VENDOR_AMLOGIC: u8 = 0x0a;
+
+/// The kind of memory layout described by a `Modifier`, ordered from the
+/// least to the most efficient for the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Layout {
+    /// The layout is chosen by the driver and not shared with other devices.
+    Implicit,
+    /// The buffer is laid out row by row.
+    Linear,
+    /// The buffer is laid out in tiles.
+    Tiled,
+    /// The buffer is tiled and compressed.
+    Compressed
+}
+
+impl fmt::Debug for Modifier {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Modifier::LINEAR => write!(fmt, "Modifier::LINEAR"),
+            Modifier::INVALID => write!(fmt, "Modifier::INVALID"),
+            Modifier(value) => write!(fmt, "Modifier({:#018x})", value)
+        }
+    }
+}
+
+impl From<u64> for Modifier {
+    fn from(value: u64) -> Modifier {
+        Modifier(value)
+    }
+}
+
+impl From<Modifier> for u64 {
+    fn from(modifier: Modifier) -> u64 {
+        modifier.0
+    }
+}
@@ -0,0 +1,203 @@
+//! Implementations of the `drm` crate's buffer traits, so buffers can be
+//! passed to `add_framebuffer` and `add_planar_framebuffer`.
+//!
+//! The traits cannot fail. A `Buffer` implements them by reading its layout
+//! with `Framebuffer::new`, which only fails if libgbm reports a format the
+//! `drm` crate does not know or a plane without a GEM handle, and panics
+//! then. `Framebuffer::new` reads and checks the layout up front instead,
+//! and is also how dumb buffers are passed.
+
+use drm::buffer::{self as drm_buffer, DrmFourcc, DrmModifier, Handle, PlanarBuffer};
+use drm::control;
+
+use std::convert::TryFrom;
+use std::fs::File;
+
+use super::{Buffer, Modifier, dumb};
+use error::{Result, Error};
+
+/// Converts a fourcc code reported by libgbm or a dumb buffer.
+fn drm_format(code: u32) -> Result<DrmFourcc> {
+    DrmFourcc::try_from(code).map_err(|_| Error::UnknownFormat(code))
+}
+
+/// Converts a GEM handle, which the kernel never makes zero.
+fn drm_handle(handle: u32) -> Result<Handle> {
+    control::from_u32(handle).ok_or(Error::Unsupported("buffers without a GEM handle"))
+}
+
+/// The layout of a buffer, checked to be usable as a KMS framebuffer.
+///
+/// Implements the `drm` crate's `Buffer` and `PlanarBuffer` traits, to be
+/// passed to `add_framebuffer` and `add_planar_framebuffer`.
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    size: (u32, u32),
+    format: DrmFourcc,
+    modifier: Option<DrmModifier>,
+    handles: [Option<Handle>; 4],
+    pitches: [u32; 4],
+    offsets: [u32; 4]
+}
+
+impl Framebuffer {
+    /// Reads the layout of a libgbm buffer.
+    ///
+    /// The modifier is left out for buffers allocated with an implicit
+    /// modifier, or when libgbm cannot report it. Fails if the format is
+    /// unknown to the `drm` crate, a plane has no GEM handle or the planes
+    /// cannot be queried.
+    pub fn new<F>(buffer: &Buffer<F>) -> Result<Framebuffer> where F: AsRef<File> {
+        let format = try!(drm_format(buffer.format()));
+        let modifier = match buffer.modifier() {
+            Ok(Modifier::INVALID) | Err(_) => None,
+            Ok(modifier) => Some(DrmModifier::from(modifier.0))
+        };
+
+        let mut framebuffer = Framebuffer {
+            size: buffer.size(),
+            format: format,
+            modifier: modifier,
+            handles: [None; 4],
+            pitches: [buffer.stride(), 0, 0, 0],
+            offsets: [0; 4]
+        };
+        for (index, plane) in try!(buffer.planes()).iter().enumerate().take(4) {
+            let handle = buffer.handle_for_plane(index as u32).unwrap_or_else(|_| buffer.raw.handle_u32());
+            framebuffer.handles[index] = Some(try!(drm_handle(handle)));
+            framebuffer.pitches[index] = plane.stride;
+            framebuffer.offsets[index] = plane.offset;
+        }
+        Ok(framebuffer)
+    }
+
+    /// Reads the layout of a dumb buffer.
+    pub fn from_dumb<F>(buffer: &dumb::Buffer<F>) -> Result<Framebuffer> where F: AsRef<File> {
+        let framebuffer = Framebuffer {
+            size: buffer.size(),
+            format: try!(drm_format(buffer.format())),
+            modifier: Some(DrmModifier::Linear),
+            handles: [Some(try!(drm_handle(buffer.handle()))), None, None, None],
+            pitches: [buffer.stride(), 0, 0, 0],
+            offsets: [0; 4]
+        };
+        Ok(framebuffer)
+    }
+}
+
+/// Reads the layout of a buffer for the trait implementations, which cannot
+/// report errors.
+fn layout<F>(buffer: &Buffer<F>) -> Framebuffer where F: AsRef<File> {
+    match Framebuffer::new(buffer) {
+        Ok(framebuffer) => framebuffer,
+        Err(err) => panic!("buffer cannot be used as a framebuffer: {}", err)
+    }
+}
+
+/// Panics if the layout cannot be read, see `Framebuffer::new`.
+impl<'a, F> drm_buffer::Buffer for Buffer<'a, F> where F: AsRef<File> {
+    fn size(&self) -> (u32, u32) {
+        Buffer::size(self)
+    }
+
+    fn format(&self) -> DrmFourcc {
+        drm_buffer::Buffer::format(&layout(self))
+    }
+
+    fn pitch(&self) -> u32 {
+        self.stride()
+    }
+
+    fn handle(&self) -> Handle {
+        drm_buffer::Buffer::handle(&layout(self))
+    }
+}
+
+/// Panics if the layout cannot be read, see `Framebuffer::new`.
+impl<'a, F> PlanarBuffer for Buffer<'a, F> where F: AsRef<File> {
+    fn size(&self) -> (u32, u32) {
+        Buffer::size(self)
+    }
+
+    fn format(&self) -> DrmFourcc {
+        PlanarBuffer::format(&layout(self))
+    }
+
+    fn modifier(&self) -> Option<DrmModifier> {
+        PlanarBuffer::modifier(&layout(self))
+    }
+
+    fn pitches(&self) -> [u32; 4] {
+        layout(self).pitches
+    }
+
+    fn handles(&self) -> [Option<Handle>; 4] {
+        layout(self).handles
+    }
+
+    fn offsets(&self) -> [u32; 4] {
+        layout(self).offsets
+    }
+}
+
+impl drm_buffer::Buffer for Framebuffer {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn format(&self) -> DrmFourcc {
+        self.format
+    }
+
+    fn pitch(&self) -> u32 {
+        self.pitches[0]
+    }
+
+    fn handle(&self) -> Handle {
+        self.handles[0].expect("framebuffers always have a first plane")
+    }
+}
+
+impl PlanarBuffer for Framebuffer {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn format(&self) -> DrmFourcc {
+        self.format
+    }
+
+    fn modifier(&self) -> Option<DrmModifier> {
+        self.modifier
+    }
+
+    fn pitches(&self) -> [u32; 4] {
+        self.pitches
+    }
+
+    fn handles(&self) -> [Option<Handle>; 4] {
+        self.handles
+    }
+
+    fn offsets(&self) -> [u32; 4] {
+        self.offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{drm_format, drm_handle};
+    use Format;
+
+    #[test]
+    fn fourcc_mapping() {
+        for &format in Format::all() {
+            assert_eq!(drm_format(format as u32).unwrap() as u32, format as u32);
+        }
+        assert!(drm_format(0).is_err());
+        assert!(drm_format(0x2020_2020).is_err());
+
+        assert!(drm_handle(0).is_err());
+        assert!(drm_handle(1).is_ok());
+    }
+}
@@ -0,0 +1,221 @@
+//! Passing buffers between processes over Unix sockets.
+//!
+//! A buffer is sent as one fixed-size message holding its
+//! `DmabufDescriptor`, with the dma-buf of every plane attached as
+//! `SCM_RIGHTS` ancillary data. The receiving process imports the dma-bufs
+//! into its own `Device`.
+//!
+//! Messages are in native byte order, as both ends run on the same
+//! machine.
+
+use libc;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+
+use super::{Buffer, BufferFlags, Device, DmabufDescriptor, Format, Modifier, Plane};
+use error::{Result, Error};
+
+/// The version of the message layout.
+pub const MESSAGE_VERSION: u32 = 1;
+
+/// The size of a message, without its file descriptors.
+pub const MESSAGE_SIZE: usize = 64;
+
+/// The number of planes a message can describe.
+pub const MAX_PLANES: usize = 4;
+
+/// Encodes a descriptor as a message.
+pub fn to_bytes(descriptor: &DmabufDescriptor) -> Result<[u8; MESSAGE_SIZE]> {
+    let planes = &descriptor.planes;
+    if planes.is_empty() || planes.len() > MAX_PLANES {
+        return Err(Error::InvalidPlaneCount { expected: MAX_PLANES, actual: planes.len() });
+    }
+
+    let mut bytes = [0; MESSAGE_SIZE];
+    bytes[0..4].copy_from_slice(&MESSAGE_VERSION.to_ne_bytes());
+    bytes[4..8].copy_from_slice(&descriptor.width.to_ne_bytes());
+    bytes[8..12].copy_from_slice(&descriptor.height.to_ne_bytes());
+    bytes[12..16].copy_from_slice(&(descriptor.format as u32).to_ne_bytes());
+    bytes[16..24].copy_from_slice(&descriptor.modifier.0.to_ne_bytes());
+    bytes[24..28].copy_from_slice(&(planes.len() as u32).to_ne_bytes());
+    bytes[28..32].copy_from_slice(&descriptor.usage.bits().to_ne_bytes());
+    for (i, plane) in planes.iter().enumerate() {
+        let start = 32 + i * 8;
+        bytes[start..start + 4].copy_from_slice(&plane.offset.to_ne_bytes());
+        bytes[start + 4..start + 8].copy_from_slice(&plane.stride.to_ne_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Decodes a message. Usage flags unknown to this crate are dropped.
+pub fn from_bytes(bytes: &[u8]) -> Result<DmabufDescriptor> {
+    if bytes.len() != MESSAGE_SIZE {
+        return Err(Error::InvalidLength { expected: MESSAGE_SIZE, actual: bytes.len() });
+    }
+    let u32_at = |offset: usize| {
+        let mut value = [0; 4];
+        value.copy_from_slice(&bytes[offset..offset + 4]);
+        u32::from_ne_bytes(value)
+    };
+
+    if u32_at(0) != MESSAGE_VERSION {
+        return Err(Error::Unsupported("buffer message version"));
+    }
+    let code = u32_at(12);
+    let format = try!(Format::from_fourcc(code).ok_or(Error::UnknownFormat(code)));
+    let mut modifier = [0; 8];
+    modifier.copy_from_slice(&bytes[16..24]);
+    let count = u32_at(24) as usize;
+    if count == 0 || count > MAX_PLANES {
+        return Err(Error::InvalidPlaneCount { expected: MAX_PLANES, actual: count });
+    }
+
+    let descriptor = DmabufDescriptor {
+        width: u32_at(4),
+        height: u32_at(8),
+        format: format,
+        modifier: Modifier(u64::from_ne_bytes(modifier)),
+        planes: (0..count).map(|i| Plane { offset: u32_at(32 + i * 8), stride: u32_at(36 + i * 8) }).collect(),
+        usage: BufferFlags::from_bits_truncate(u32_at(28))
+    };
+    Ok(descriptor)
+}
+
+/// Sends a descriptor with one file descriptor per plane.
+pub fn send(stream: &UnixStream, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<()> {
+    let bytes = try!(to_bytes(descriptor));
+    if fds.len() != descriptor.planes.len() {
+        return Err(Error::InvalidPlaneCount { expected: descriptor.planes.len(), actual: fds.len() });
+    }
+    send_message(stream, &bytes, fds)
+}
+
+/// Sends the bytes of a message, with the file descriptors attached to the
+/// first of them.
+fn send_message(stream: &UnixStream, bytes: &[u8], fds: &[BorrowedFd]) -> Result<()> {
+    let fds_size = fds.len() * mem::size_of::<libc::c_int>();
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size as u32) } as usize];
+    let mut iov = libc::iovec { iov_base: bytes.as_ptr() as *mut _, iov_len: bytes.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut _;
+    msg.msg_controllen = control.len() as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size as u32) as _;
+        let data = libc::CMSG_DATA(cmsg) as *mut libc::c_int;
+        for (i, fd) in fds.iter().enumerate() {
+            ptr::write_unaligned(data.add(i), fd.as_raw_fd());
+        }
+    }
+
+    let sent = unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // The descriptors went with the first byte, so the rest is plain data.
+    try!((&*stream).write_all(&bytes[sent as usize..]));
+    Ok(())
+}
+
+/// Receives a descriptor and the file descriptors of its planes.
+pub fn recv(stream: &UnixStream) -> Result<(DmabufDescriptor, Vec<OwnedFd>)> {
+    let mut bytes = [0u8; MESSAGE_SIZE];
+    let fds_size = MAX_PLANES * mem::size_of::<libc::c_int>();
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size as u32) } as usize];
+    let mut iov = libc::iovec { iov_base: bytes.as_mut_ptr() as *mut _, iov_len: bytes.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut _;
+    msg.msg_controllen = control.len() as _;
+
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    // Take ownership of the descriptors first, so they are closed on error.
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg) as *const libc::c_int;
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<libc::c_int>();
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if received == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        // The kernel closed the descriptors that did not fit, so how many
+        // were sent is unknown. Those that arrived are closed here.
+        drop(fds);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "more file descriptors than planes were sent").into());
+    }
+    try!((&*stream).read_exact(&mut bytes[received as usize..]));
+
+    let descriptor = try!(from_bytes(&bytes));
+    if fds.len() != descriptor.planes.len() {
+        return Err(Error::InvalidPlaneCount { expected: descriptor.planes.len(), actual: fds.len() });
+    }
+    Ok((descriptor, fds))
+}
+
+/// Sends a buffer, exporting a dma-buf for each of its planes.
+pub fn send_buffer<F>(stream: &UnixStream, buffer: &Buffer<F>) -> Result<()> where F: AsRef<File> {
+    let descriptor = try!(buffer.descriptor());
+    let fds = try!(buffer.plane_fds());
+    let fds: Vec<BorrowedFd> = fds.iter().map(|fd| fd.as_fd()).collect();
+    send(stream, &descriptor, &fds)
+}
+
+/// Receives a buffer and imports it into a device, with the usage it was
+/// sent with.
+pub fn recv_buffer<'a, F>(stream: &UnixStream, device: &'a Device<F>) -> Result<Buffer<'a, F>> where F: AsRef<File> {
+    let (descriptor, fds) = try!(recv(stream));
+    let fds: Vec<BorrowedFd> = fds.iter().map(|fd| fd.as_fd()).collect();
+    device.import_dmabuf(&descriptor, &fds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{recv, send_message, to_bytes, MAX_PLANES};
+    use {DmabufDescriptor, Format, RENDERING};
+    use error::Error;
+
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{AsFd, BorrowedFd};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn too_many_fds() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let file = File::open("/dev/null").unwrap();
+        let descriptor = DmabufDescriptor::linear((16, 16), Format::XRGB8888, 64, RENDERING);
+        let bytes = to_bytes(&descriptor).unwrap();
+
+        let fds: Vec<BorrowedFd> = (0..MAX_PLANES + 1).map(|_| file.as_fd()).collect();
+        send_message(&a, &bytes, &fds).unwrap();
+        match recv(&b) {
+            Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::InvalidData => (),
+            _ => panic!("a message with too many descriptors was received")
+        }
+    }
+}
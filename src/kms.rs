@@ -0,0 +1,81 @@
+//! Parsing of KMS plane properties.
+//!
+//! The formats and modifiers a plane can scan out are reported by its
+//! `IN_FORMATS` property, a blob holding a `drm_format_modifier_blob`. The
+//! blob itself is read with the DRM library of choice; this module only
+//! decodes its bytes.
+
+use super::{Format, Modifier};
+use error::{Result, Error};
+
+/// The first version of `drm_format_modifier_blob`. Later versions keep its
+/// layout, so they are understood by the parser as well.
+pub const FORMAT_BLOB_VERSION: u32 = 1;
+
+/// The size of the `drm_format_modifier_blob` header.
+const HEADER_SIZE: usize = 24;
+
+/// The size of a `drm_format_modifier` entry.
+const MODIFIER_SIZE: usize = 24;
+
+/// Parses an `IN_FORMATS` blob into the format and modifier pairs the plane
+/// supports.
+///
+/// Pairs are grouped by format, in the order the blob lists formats, so the
+/// result can be passed to `negotiate::Negotiator::consumer` as it is.
+/// Formats without a `Format` variant are skipped.
+pub fn parse_in_formats(blob: &[u8]) -> Result<Vec<(Format, Modifier)>> {
+    if blob.len() < HEADER_SIZE {
+        return Err(Error::InvalidLength { expected: HEADER_SIZE, actual: blob.len() });
+    }
+
+    let version = read_u32(blob, 0);
+    if version < FORMAT_BLOB_VERSION {
+        return Err(Error::Unsupported("IN_FORMATS blob version"));
+    }
+    let count_formats = read_u32(blob, 8) as usize;
+    let formats_offset = read_u32(blob, 12) as usize;
+    let count_modifiers = read_u32(blob, 16) as usize;
+    let modifiers_offset = read_u32(blob, 20) as usize;
+
+    // Computed in 64 bits so offsets from a bad blob cannot overflow.
+    let formats_end = formats_offset as u64 + count_formats as u64 * 4;
+    let modifiers_end = modifiers_offset as u64 + count_modifiers as u64 * MODIFIER_SIZE as u64;
+    let expected = formats_end.max(modifiers_end);
+    if (blob.len() as u64) < expected {
+        return Err(Error::InvalidLength { expected: expected as usize, actual: blob.len() });
+    }
+
+    let modifiers: Vec<(u64, usize, Modifier)> = (0..count_modifiers).map(|i| {
+        let entry = modifiers_offset + i * MODIFIER_SIZE;
+        (read_u64(blob, entry), read_u32(blob, entry + 8) as usize, Modifier(read_u64(blob, entry + 16)))
+    }).collect();
+
+    let mut pairs = Vec::new();
+    for index in 0..count_formats {
+        let format = match Format::from_fourcc(read_u32(blob, formats_offset + index * 4)) {
+            Some(format) => format,
+            None => continue
+        };
+
+        // Each entry covers the 64 formats starting at its offset.
+        for &(mask, offset, modifier) in &modifiers {
+            if index >= offset && index - offset < 64 && mask & (1 << (index - offset)) != 0 {
+                pairs.push((format, modifier));
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+fn read_u32(blob: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&blob[offset..offset + 4]);
+    u32::from_ne_bytes(bytes)
+}
+
+fn read_u64(blob: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&blob[offset..offset + 8]);
+    u64::from_ne_bytes(bytes)
+}
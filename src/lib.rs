@@ -1,16 +1,76 @@
 #[macro_use]
 extern crate bitflags;
 extern crate errno;
+extern crate libc;
+#[cfg(feature = "drm")]
+extern crate drm;
+#[cfg(feature = "egl")]
+extern crate khronos_egl;
+#[cfg(feature = "vulkan")]
+extern crate ash;
+#[cfg(feature = "raw-window-handle")]
+extern crate raw_window_handle;
+#[cfg(feature = "wayland-client")]
+extern crate wayland_client;
+#[cfg(feature = "wayland-server")]
+extern crate wayland_server;
+#[cfg(any(feature = "wayland-client", feature = "wayland-server"))]
+extern crate wayland_protocols;
+#[cfg(feature = "x11")]
+extern crate x11rb;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
 
 mod ffi;
+mod allocator;
+mod builder;
+mod cursor;
+mod descriptor;
+mod format;
+#[cfg(feature = "drm")]
+mod framebuffer;
+#[cfg(feature = "raw-window-handle")]
+mod window;
 pub mod error;
-use error::Result;
+#[cfg(feature = "egl")]
+pub mod egl;
+pub mod feedback;
+pub mod dumb;
+pub mod ipc;
+pub mod kms;
+pub mod negotiate;
+pub mod node;
+pub mod prime;
+pub mod software;
+#[cfg(feature = "vulkan")]
+pub mod vulkan;
+#[cfg(any(feature = "wayland-client", feature = "wayland-server"))]
+pub mod wayland;
+#[cfg(feature = "x11")]
+pub mod x11;
+use error::{Result, Error};
+
+pub use allocator::{Allocator, BufferObject};
+pub use builder::{BufferBuilder, BuildConfig, Built, SurfaceBuilder};
+pub use cursor::Cursor;
+pub use descriptor::DmabufDescriptor;
+pub use format::{Format, Layout, Modifier, fourcc};
+#[cfg(feature = "drm")]
+pub use framebuffer::Framebuffer;
+#[cfg(feature = "raw-window-handle")]
+pub use window::Window;
 
+use std::fmt;
 use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
 use std::os::raw::c_void;
 use std::rc::Rc;
 use std::marker::PhantomData;
+use std::slice;
 
 /// A `Device` is a handle to the character device file that provides libgbm
 /// access.
@@ -39,43 +99,321 @@ impl<'a, F> Device<F> where F: AsRef<File> {
         Ok(dev)
     }
 
+    /// Returns the usage flags the device supports.
+    ///
+    /// Flags newer than the first libgbm releases cannot be queried, so the
+    /// first call allocates a small buffer with each of them to find out.
+    pub fn supported_flags(&self) -> BufferFlags {
+        BufferFlags::from_bits_truncate(self.raw.supported_flags())
+    }
+
+    /// Describes which optional parts of the libgbm API the loaded library
+    /// supports.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::new(ffi::optional(), self.supported_flags())
+    }
+
+    /// Returns the name libgbm gives to a fourcc format code, such as the
+    /// value of `Buffer::format`.
+    pub fn format_name(&self, format: u32) -> Result<String> {
+        ffi::format_name(format)
+    }
+
+    /// Fails if `flags` contains bits the device does not support, rather
+    /// than silently passing them on.
+    fn check_flags(&self, flags: BufferFlags) -> Result<()> {
+        check_flags(flags, |flag| self.raw.supports_flag(flag))
+    }
+
     /// Creates a `Buffer` using the given size and parameters.
     pub fn buffer(&'a self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Buffer<F>> {
+        try!(self.check_flags(flags));
         let (width, height) = size;
         let buffer = Buffer {
             device: PhantomData,
             raw: try!(ffi::GbmBufferObject::new(&self.raw, width, height, format as u32, flags.bits())),
-            surface: None
+            surface: None,
+            usage: flags
+        };
+        Ok(buffer)
+    }
+
+    /// Creates a `Buffer` with one of the given modifiers, leaving the choice
+    /// to the driver, such as the modifiers of a `negotiate::Choice`.
+    ///
+    /// `Modifier::INVALID` is dropped from the list, and if nothing else is
+    /// left the buffer is created like `buffer` does. If libgbm cannot take
+    /// modifiers but the list allows `Modifier::LINEAR`, a buffer is created
+    /// with the `LINEAR` flag instead, which `usage` then includes.
+    pub fn buffer_with_modifiers(&'a self, size: (u32, u32), format: Format, modifiers: &[Modifier], flags: BufferFlags) -> Result<Buffer<F>> {
+        let modifiers = explicit_modifiers(modifiers);
+        if modifiers.is_empty() {
+            return self.buffer(size, format, flags);
+        }
+
+        try!(self.check_flags(flags));
+        let (width, height) = size;
+        let (raw, usage) = try!(linear_fallback(&modifiers, flags,
+            || ffi::GbmBufferObject::with_modifiers(&self.raw, width, height, format as u32, &modifiers, flags.bits()),
+            |usage| ffi::GbmBufferObject::new(&self.raw, width, height, format as u32, usage.bits())));
+        let buffer = Buffer {
+            device: PhantomData,
+            raw: raw,
+            surface: None,
+            usage: usage
         };
         Ok(buffer)
     }
 
+    /// Creates a `Buffer` with the size, format, modifier and usage of a
+    /// descriptor, such as one received from another process.
+    ///
+    /// The plane layout is left to the driver. A descriptor with
+    /// `Modifier::INVALID` gets a buffer like `buffer` creates.
+    pub fn buffer_like(&'a self, descriptor: &DmabufDescriptor) -> Result<Buffer<F>> {
+        self.buffer_with_modifiers(descriptor.size(), descriptor.format, &[descriptor.modifier], descriptor.usage)
+    }
+
+    /// Imports a single-plane dma-buf as a `Buffer`.
+    ///
+    /// The file descriptor is only borrowed; the buffer keeps its own
+    /// reference to the underlying memory.
+    pub fn import_fd(&'a self, fd: BorrowedFd, size: (u32, u32), stride: u32, format: Format, flags: BufferFlags) -> Result<Buffer<F>> {
+        try!(self.check_flags(flags));
+        let (width, height) = size;
+        let buffer = Buffer {
+            device: PhantomData,
+            raw: try!(ffi::GbmBufferObject::import_fd(&self.raw, fd.as_raw_fd(), width, height, stride, format as u32, flags.bits())),
+            surface: None,
+            usage: flags
+        };
+        Ok(buffer)
+    }
+
+    /// Imports a dma-buf with one file descriptor per plane as a `Buffer`,
+    /// using the layout and usage of a descriptor.
+    ///
+    /// The file descriptors are only borrowed, and may refer to the same
+    /// dma-buf for several planes.
+    pub fn import_dmabuf(&'a self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Buffer<F>> {
+        let planes = &descriptor.planes;
+        try!(self.check_flags(descriptor.usage));
+        if planes.is_empty() || planes.len() > ffi::GBM_MAX_PLANES {
+            return Err(Error::InvalidPlaneCount { expected: ffi::GBM_MAX_PLANES, actual: planes.len() });
+        }
+        if fds.len() != planes.len() {
+            return Err(Error::InvalidPlaneCount { expected: planes.len(), actual: fds.len() });
+        }
+
+        let mut data = ffi::gbm_import_fd_modifier_data {
+            width: descriptor.width,
+            height: descriptor.height,
+            format: descriptor.format as u32,
+            num_fds: fds.len() as u32,
+            modifier: descriptor.modifier.0,
+            ..Default::default()
+        };
+        for (i, (fd, plane)) in fds.iter().zip(planes).enumerate() {
+            data.fds[i] = fd.as_raw_fd();
+            data.strides[i] = plane.stride as i32;
+            data.offsets[i] = plane.offset as i32;
+        }
+
+        let buffer = Buffer {
+            device: PhantomData,
+            raw: try!(ffi::GbmBufferObject::import_fd_modifier(&self.raw, &data, descriptor.usage.bits())),
+            surface: None,
+            usage: descriptor.usage
+        };
+        Ok(buffer)
+    }
+
+    /// Returns true if the device supports buffers of the given format and
+    /// usage.
+    pub fn is_format_supported(&self, format: Format, flags: BufferFlags) -> bool {
+        self.raw.is_format_supported(format as u32, flags.bits())
+    }
+
+    /// Returns true if the device supports buffers of the given format and
+    /// modifier.
+    pub fn is_modifier_supported(&self, format: Format, modifier: Modifier) -> Result<bool> {
+        let count = try!(self.raw.format_modifier_plane_count(format as u32, modifier.0));
+        Ok(count.is_some())
+    }
+
+    /// Starts building a `Buffer` that can fall back on other formats and
+    /// modifiers.
+    pub fn buffer_builder(&'a self) -> BufferBuilder<'a, F> {
+        BufferBuilder::new(self)
+    }
+
     /// Creates a `Surface` using the given size and parameters.
     pub fn surface(&'a self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Surface<F>> {
         Surface::from_device(self, size, format, flags)
     }
 
+    /// Creates a `Surface` with one of the given modifiers, see
+    /// `buffer_with_modifiers`.
+    pub fn surface_with_modifiers(&'a self, size: (u32, u32), format: Format, modifiers: &[Modifier], flags: BufferFlags) -> Result<Surface<F>> {
+        Surface::with_modifiers(self, size, format, modifiers, flags)
+    }
+
+    /// Starts building a `Surface` that can fall back on other formats and
+    /// modifiers.
+    pub fn surface_builder(&'a self) -> SurfaceBuilder<'a, F> {
+        SurfaceBuilder::new(self)
+    }
+
+    /// Creates a `Cursor` of the default 64x64 size.
+    pub fn cursor(&'a self) -> Result<Cursor<F>> {
+        Cursor::from_device(self, cursor::DEFAULT_SIZE, cursor::DEFAULT_BUFFERS)
+    }
+
+    /// Creates a `Cursor` of the given size, as reported by the DRM cursor
+    /// width and height caps.
+    pub fn cursor_with_size(&'a self, size: (u32, u32)) -> Result<Cursor<F>> {
+        Cursor::from_device(self, size, cursor::DEFAULT_BUFFERS)
+    }
+
+    /// Creates a dumb buffer allocator on the same file, to fall back on
+    /// when libgbm refuses a format.
+    pub fn dumb(&self) -> dumb::Device<&Device<F>> {
+        dumb::Device::from_file(self)
+    }
+
     /// Returns a pointer to the underlying `gbm_device`
     pub unsafe fn raw(&self) -> *mut c_void {
         self.raw.raw as *mut _
     }
 }
 
+/// Returns the modifiers to pass to libgbm, without `Modifier::INVALID`.
+fn explicit_modifiers(modifiers: &[Modifier]) -> Vec<u64> {
+    modifiers.iter().filter(|&&modifier| modifier != Modifier::INVALID).map(|modifier| modifier.0).collect()
+}
+
+/// Creates a buffer or surface with explicit modifiers, or with the `LINEAR`
+/// flag if libgbm cannot take modifiers but they allow a linear layout.
+/// Returns the usage it was created with.
+fn linear_fallback<T, M, L>(modifiers: &[u64], flags: BufferFlags, with_modifiers: M, linear: L) -> Result<(T, BufferFlags)>
+    where M: FnOnce() -> Result<T>, L: FnOnce(BufferFlags) -> Result<T>
+{
+    match with_modifiers() {
+        Err(Error::Unsupported(_)) if modifiers.contains(&Modifier::LINEAR.0) => {
+            let usage = flags | LINEAR;
+            linear(usage).map(|value| (value, usage))
+        },
+        result => result.map(|value| (value, flags))
+    }
+}
+
+/// Fails with the flags `supports` rejects, see `ffi::unsupported_flags`.
+fn check_flags<S>(flags: BufferFlags, supports: S) -> Result<()> where S: FnMut(u32) -> bool {
+    let unsupported = ffi::unsupported_flags(flags.bits(), supports);
+    if unsupported != 0 {
+        return Err(Error::UnsupportedFlags(BufferFlags::from_bits_truncate(unsupported)));
+    }
+    Ok(())
+}
+
+/// Describes what the loaded libgbm supports.
+///
+/// Methods that need a missing part of the API return
+/// `Error::Unsupported` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Buffers and surfaces can be created with explicit modifiers, and a
+    /// buffer's modifier can be queried.
+    pub modifiers: bool,
+    /// Usage flags can be combined with explicit modifiers.
+    pub modifier_flags: bool,
+    /// The device can be asked whether it supports a format and modifier.
+    pub modifier_queries: bool,
+    /// The stride, offset and handle of each plane can be queried.
+    pub planes: bool,
+    /// Each plane can be exported as its own file descriptor.
+    pub plane_fds: bool,
+    /// The bits per pixel of a buffer can be queried.
+    pub bpp: bool,
+    /// Format codes can be turned into names.
+    pub format_names: bool,
+    /// The usage flags the library understands.
+    pub flags: BufferFlags
+}
+
+impl Capabilities {
+    fn new(optional: &ffi::Optional, flags: BufferFlags) -> Capabilities {
+        Capabilities {
+            modifiers: optional.gbm_bo_get_modifier.is_some() &&
+                       optional.gbm_bo_create_with_modifiers.is_some() &&
+                       optional.gbm_surface_create_with_modifiers.is_some(),
+            modifier_flags: optional.gbm_bo_create_with_modifiers2.is_some() &&
+                            optional.gbm_surface_create_with_modifiers2.is_some(),
+            modifier_queries: optional.gbm_device_get_format_modifier_plane_count.is_some(),
+            planes: optional.gbm_bo_get_plane_count.is_some() &&
+                    optional.gbm_bo_get_stride_for_plane.is_some() &&
+                    optional.gbm_bo_get_offset.is_some() &&
+                    optional.gbm_bo_get_handle_for_plane.is_some(),
+            plane_fds: optional.gbm_bo_get_fd_for_plane.is_some(),
+            bpp: optional.gbm_bo_get_bpp.is_some(),
+            format_names: optional.gbm_format_get_name.is_some(),
+            flags: flags
+        }
+    }
+}
+
 /// A `Surface` is a handle to the buffers used for primary rendering.
 ///
 /// A `Surface` cannot outlive the `Device` it was created from.
 pub struct Surface<F> where F: AsRef<File> {
     device: PhantomData<Device<F>>,
     raw: ffi::GbmSurface,
+    format: Format,
+    usage: BufferFlags
+}
+
+impl<F> fmt::Debug for Surface<F> where F: AsRef<File> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Surface")
+            .field("raw", &self.raw.raw)
+            .field("format", &self.format)
+            .field("usage", &self.usage)
+            .finish()
+    }
 }
 
 impl<'a, F> Surface<F> where F: AsRef<File> {
     /// Creates a surface from a `Device` and the given parameters.
     pub fn from_device(device: &'a Device<F>, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Surface<F>> {
+        try!(device.check_flags(flags));
         let (width, height) = size;
         let surface = Surface {
             device: PhantomData,
-            raw: try!(ffi::GbmSurface::new(&device.raw, width, height, format as u32, flags.bits()))
+            raw: try!(ffi::GbmSurface::new(&device.raw, width, height, format as u32, flags.bits())),
+            format: format,
+            usage: flags
+        };
+        Ok(surface)
+    }
+
+    /// Creates a surface from a `Device` with one of the given modifiers,
+    /// see `Device::buffer_with_modifiers`.
+    pub fn with_modifiers(device: &'a Device<F>, size: (u32, u32), format: Format, modifiers: &[Modifier], flags: BufferFlags) -> Result<Surface<F>> {
+        let modifiers = explicit_modifiers(modifiers);
+        if modifiers.is_empty() {
+            return Surface::from_device(device, size, format, flags);
+        }
+
+        try!(device.check_flags(flags));
+        let (width, height) = size;
+        let (raw, usage) = try!(linear_fallback(&modifiers, flags,
+            || ffi::GbmSurface::with_modifiers(&device.raw, width, height, format as u32, &modifiers, flags.bits()),
+            |usage| ffi::GbmSurface::new(&device.raw, width, height, format as u32, usage.bits())));
+        let surface = Surface {
+            device: PhantomData,
+            raw: raw,
+            format: format,
+            usage: usage
         };
         Ok(surface)
     }
@@ -90,11 +428,17 @@ impl<'a, F> Surface<F> where F: AsRef<File> {
         let buffer = Buffer {
             device: PhantomData,
             raw: try!(self.raw.lock_front_buffer()),
-            surface: Some(self)
+            surface: Some(self),
+            usage: self.usage
         };
         Ok(buffer)
     }
 
+    /// Returns the format the surface was created with.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
     /// Returns a pointer to the underlying `gbm_surface`
     pub unsafe fn raw(&self) -> *mut c_void {
         self.raw.raw as *mut _
@@ -104,7 +448,19 @@ impl<'a, F> Surface<F> where F: AsRef<File> {
 pub struct Buffer<'a, F> where F: 'a + AsRef<File> {
     device: PhantomData<Device<F>>,
     raw: ffi::GbmBufferObject,
-    surface: Option<&'a Surface<F>>
+    surface: Option<&'a Surface<F>>,
+    usage: BufferFlags
+}
+
+impl<'a, F> fmt::Debug for Buffer<'a, F> where F: AsRef<File> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Buffer")
+            .field("raw", &self.raw.raw)
+            .field("size", &self.size())
+            .field("format", &format_args!("{:#010x}", self.format()))
+            .field("usage", &self.usage)
+            .finish()
+    }
 }
 
 impl<'a, F> Buffer<'a, F> where F: AsRef<File> {
@@ -128,6 +484,151 @@ impl<'a, F> Buffer<'a, F> where F: AsRef<File> {
         self.raw.handle()
     }
 
+    /// Exports the buffer as a dma-buf file descriptor.
+    pub fn fd(&self) -> Result<OwnedFd> {
+        let fd = try!(self.raw.fd());
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Exports the given plane of the buffer as a dma-buf file descriptor.
+    pub fn fd_for_plane(&self, plane: u32) -> Result<OwnedFd> {
+        let fd = try!(self.raw.fd_for_plane(plane));
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Maps the whole buffer into memory for CPU access.
+    ///
+    /// The buffer is unmapped when the returned `Mapping` is dropped.
+    pub fn map(&mut self, flags: TransferFlags) -> Result<Mapping> {
+        let (width, height) = self.size();
+        let (data, stride, map_data) = try!(self.raw.map(0, 0, width, height, flags.bits()));
+        let mapping = Mapping {
+            raw: &self.raw,
+            data: data,
+            map_data: map_data,
+            stride: stride,
+            len: stride as usize * height as usize
+        };
+        Ok(mapping)
+    }
+
+    /// Returns the format modifier of the buffer.
+    pub fn modifier(&self) -> Result<Modifier> {
+        self.raw.modifier().map(Modifier)
+    }
+
+    /// Returns the number of planes in the buffer.
+    pub fn plane_count(&self) -> Result<u32> {
+        self.raw.plane_count()
+    }
+
+    /// Returns the stride of the given plane.
+    pub fn stride_for_plane(&self, plane: u32) -> Result<u32> {
+        self.raw.stride_for_plane(plane)
+    }
+
+    /// Returns the offset of the given plane.
+    pub fn offset(&self, plane: u32) -> Result<u32> {
+        self.raw.offset(plane)
+    }
+
+    /// Returns the GEM handle of the given plane.
+    pub fn handle_for_plane(&self, plane: u32) -> Result<u32> {
+        self.raw.handle_for_plane(plane)
+    }
+
+    /// Returns the offset and stride of every plane in the buffer.
+    ///
+    /// Buffers from a libgbm without per-plane queries report a single plane.
+    pub fn planes(&self) -> Result<Vec<Plane>> {
+        if ffi::optional().gbm_bo_get_plane_count.is_none() {
+            return Ok(vec![Plane { offset: 0, stride: self.stride() }]);
+        }
+
+        let mut planes = Vec::new();
+        for plane in 0..try!(self.plane_count()) {
+            planes.push(Plane {
+                offset: try!(self.offset(plane)),
+                stride: try!(self.stride_for_plane(plane))
+            });
+        }
+        Ok(planes)
+    }
+
+    /// Describes the buffer for importing its dma-bufs elsewhere.
+    ///
+    /// Buffers whose modifier cannot be queried get `Modifier::INVALID`.
+    pub fn descriptor(&self) -> Result<DmabufDescriptor> {
+        let code = self.format();
+        let (width, height) = self.size();
+        let descriptor = DmabufDescriptor {
+            width: width,
+            height: height,
+            format: try!(Format::from_fourcc(code).ok_or(Error::UnknownFormat(code))),
+            modifier: self.modifier().unwrap_or(Modifier::INVALID),
+            planes: try!(self.planes()),
+            usage: self.usage
+        };
+        Ok(descriptor)
+    }
+
+    /// Exports every plane of the buffer as a dma-buf file descriptor.
+    ///
+    /// With a libgbm that cannot export planes separately, the whole buffer
+    /// is exported once for each plane.
+    pub fn plane_fds(&self) -> Result<Vec<OwnedFd>> {
+        let count = try!(self.planes()).len() as u32;
+        let mut fds = Vec::new();
+        for plane in 0..count {
+            let fd = if ffi::optional().gbm_bo_get_fd_for_plane.is_some() {
+                try!(self.fd_for_plane(plane))
+            } else {
+                try!(self.fd())
+            };
+            fds.push(fd);
+        }
+        Ok(fds)
+    }
+
+    /// Returns the number of bits per pixel of the buffer.
+    pub fn bpp(&self) -> Result<u32> {
+        self.raw.bpp()
+    }
+
+    /// Returns the usage flags the buffer was created with.
+    pub fn usage(&self) -> BufferFlags {
+        self.usage
+    }
+
+    /// Writes data directly into the buffer.
+    ///
+    /// The buffer must have been created with the `WRITE` usage flag, and
+    /// `data` must cover the whole buffer, that is `height * stride` bytes.
+    /// This is the only portable way to upload hardware cursor images.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if !self.usage.contains(WRITE) {
+            return Err(Error::MissingUsage(WRITE));
+        }
+
+        let expected = self.raw.height() as usize * self.raw.stride() as usize;
+        if data.len() != expected {
+            return Err(Error::InvalidLength { expected: expected, actual: data.len() });
+        }
+
+        self.raw.write(data)
+    }
+
+    /// Writes tightly packed rows of 4-byte pixels into the buffer.
+    ///
+    /// The buffer must use a format with 32 bits per pixel, and `data` must
+    /// hold exactly `width * height` pixels. Each row is copied to the start
+    /// of the matching row in the buffer and the padding up to the buffer's
+    /// stride is cleared.
+    pub fn write_packed(&mut self, data: &[u8]) -> Result<()> {
+        let padded = try!(pad_rows(self.format(), self.size(), self.stride(), data));
+        self.write(&padded)
+    }
+
     /// Attach a reference counted object to the buffer. This can be
     /// retrieved again using `get_user_data`
     ///
@@ -163,17 +664,209 @@ impl<'a, F> Drop for Buffer<'a, F> where F: AsRef<File> {
     }
 }
 
+/// Copies tightly packed rows of 4-byte pixels into rows of `stride` bytes,
+/// for `write_packed`. Formats without 32 bits per pixel are rejected.
+fn pad_rows(code: u32, size: (u32, u32), stride: u32, data: &[u8]) -> Result<Vec<u8>> {
+    let format = try!(Format::from_fourcc(code).ok_or(Error::UnknownFormat(code)));
+    if format.bytes_per_pixel() != Some(4) {
+        return Err(Error::UnsupportedFormat(format));
+    }
+
+    let (width, height) = size;
+    let row = width as usize * 4;
+    let expected = row * height as usize;
+    if data.len() != expected {
+        return Err(Error::InvalidLength { expected: expected, actual: data.len() });
+    }
+
+    let stride = stride as usize;
+    let mut padded = vec![0u8; stride * height as usize];
+    for (src, dst) in data.chunks(row).zip(padded.chunks_mut(stride)) {
+        dst[..row].copy_from_slice(src);
+    }
+    Ok(padded)
+}
+
+/// Checks that a descriptor has a single linear plane at the start of its
+/// only dma-buf, which is all software and dumb buffers can import, and
+/// returns that dma-buf and the stride of the plane.
+fn linear_plane<'f>(descriptor: &DmabufDescriptor, fds: &[BorrowedFd<'f>]) -> Result<(BorrowedFd<'f>, u32)> {
+    if descriptor.planes.len() != 1 {
+        return Err(Error::InvalidPlaneCount { expected: 1, actual: descriptor.planes.len() });
+    }
+    if fds.len() != 1 {
+        return Err(Error::InvalidPlaneCount { expected: 1, actual: fds.len() });
+    }
+    if descriptor.modifier != Modifier::LINEAR && descriptor.modifier != Modifier::INVALID {
+        return Err(Error::Unsupported("dma-bufs with a non-linear modifier"));
+    }
+    let plane = descriptor.planes[0];
+    if plane.offset != 0 {
+        return Err(Error::Unsupported("planes at an offset"));
+    }
+    Ok((fds[0], plane.stride))
+}
+
+/// Returns the size of a file or dma-buf in bytes. `fstat` is used rather
+/// than seeking, as the file offset is shared with every duplicate of the
+/// file descriptor.
+fn file_len(fd: BorrowedFd) -> Result<u64> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(stat.st_size.max(0) as u64)
+}
+
+/// The layout of one plane of a buffer, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Plane {
+    /// The offset of the plane from the start of its dma-buf.
+    pub offset: u32,
+    /// The stride of the plane.
+    pub stride: u32
+}
+
+/// A CPU mapping of a `Buffer`, created by `Buffer::map`.
+///
+/// The mapped memory is accessed as a byte slice of `stride * height` bytes.
+pub struct Mapping<'b> {
+    raw: &'b ffi::GbmBufferObject,
+    data: *mut u8,
+    map_data: *mut c_void,
+    stride: u32,
+    len: usize
+}
+
+impl<'b> Mapping<'b> {
+    /// Returns the stride of the mapped memory, which may differ from the
+    /// stride of the buffer.
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+}
+
+impl<'b> Deref for Mapping<'b> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl<'b> DerefMut for Mapping<'b> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.data, self.len) }
+    }
+}
+
+impl<'b> Drop for Mapping<'b> {
+    fn drop(&mut self) {
+        self.raw.unmap(self.map_data);
+    }
+}
+
+bitflags! {
+    pub flags TransferFlags: u32 {
+        const TRANSFER_READ       = ffi::gbm_bo_transfer_flags::GBM_BO_TRANSFER_READ as u32,
+        const TRANSFER_WRITE      = ffi::gbm_bo_transfer_flags::GBM_BO_TRANSFER_WRITE as u32,
+        const TRANSFER_READ_WRITE = ffi::gbm_bo_transfer_flags::GBM_BO_TRANSFER_READ_WRITE as u32
+    }
+}
+
 bitflags! {
     pub flags BufferFlags: u32 {
-        const SCANOUT   = ffi::gbm_bo_flags::GBM_BO_USE_SCANOUT as u32,
-        const CURSOR    = ffi::gbm_bo_flags::GBM_BO_USE_CURSOR as u32,
-        const RENDERING = ffi::gbm_bo_flags::GBM_BO_USE_RENDERING as u32,
-        const WRITE     = ffi::gbm_bo_flags::GBM_BO_USE_WRITE as u32,
-        const LINEAR    = ffi::gbm_bo_flags::GBM_BO_USE_LINEAR as u32
+        const SCANOUT         = ffi::gbm_bo_flags::GBM_BO_USE_SCANOUT as u32,
+        const CURSOR          = ffi::gbm_bo_flags::GBM_BO_USE_CURSOR as u32,
+        const CURSOR_64X64    = ffi::GBM_BO_USE_CURSOR_64X64 as u32,
+        const RENDERING       = ffi::gbm_bo_flags::GBM_BO_USE_RENDERING as u32,
+        const WRITE           = ffi::gbm_bo_flags::GBM_BO_USE_WRITE as u32,
+        const LINEAR          = ffi::gbm_bo_flags::GBM_BO_USE_LINEAR as u32,
+        const PROTECTED       = ffi::GBM_BO_USE_PROTECTED,
+        const FRONT_RENDERING = ffi::GBM_BO_USE_FRONT_RENDERING
     }
 }
 
-pub enum Format {
-    XRGB8888 = ffi::gbm_bo_format::GBM_BO_FORMAT_XRGB8888 as isize,
-    ARGB8888 = ffi::gbm_bo_format::GBM_BO_FORMAT_ARGB8888 as isize
+#[cfg(test)]
+mod tests {
+    use super::{check_flags, ffi, linear_fallback, BufferFlags, Capabilities, Modifier, CURSOR, FRONT_RENDERING, LINEAR, PROTECTED, RENDERING, SCANOUT, WRITE};
+    use error::Error;
+
+    use std::os::raw::c_void;
+    use std::ptr::null_mut;
+
+    #[test]
+    fn flags_checked_against_mask() {
+        let check = |flags: BufferFlags, mask: BufferFlags| check_flags(flags, |flag| mask.bits() & flag != 0);
+
+        // The first flags are always supported, whatever the device says.
+        assert!(check(SCANOUT | CURSOR | RENDERING | WRITE | LINEAR, BufferFlags::empty()).is_ok());
+
+        assert!(check(SCANOUT | PROTECTED, PROTECTED).is_ok());
+        match check(SCANOUT | PROTECTED | FRONT_RENDERING, PROTECTED) {
+            Err(Error::UnsupportedFlags(flags)) => assert_eq!(flags, FRONT_RENDERING),
+            _ => panic!("front rendering was not rejected")
+        }
+        match check(PROTECTED | FRONT_RENDERING, BufferFlags::empty()) {
+            Err(Error::UnsupportedFlags(flags)) => assert_eq!(flags, PROTECTED | FRONT_RENDERING),
+            _ => panic!("newer flags were not rejected")
+        }
+
+        // Bits this crate has no flag for yet are checked the same way.
+        assert_eq!(ffi::unsupported_flags(1 << 9 | 1 << 5, |flag| flag == 1 << 5), 1 << 9);
+    }
+
+    #[test]
+    fn capabilities() {
+        let names = |names: &'static [&'static [u8]]| move |name: &[u8]| {
+            // The entry points are only checked for, never called.
+            if names.contains(&name) { capabilities as *mut c_void } else { null_mut() }
+        };
+
+        let none = unsafe { ffi::Optional::resolve(names(&[])) };
+        let caps = Capabilities::new(&none, SCANOUT);
+        assert!(!caps.modifiers && !caps.modifier_flags && !caps.modifier_queries && !caps.planes);
+        assert!(!caps.plane_fds && !caps.bpp && !caps.format_names);
+        assert_eq!(caps.flags, SCANOUT);
+
+        // Creating buffers with modifiers is no use without querying them.
+        let some = unsafe {
+            ffi::Optional::resolve(names(&[b"gbm_bo_create_with_modifiers\0", b"gbm_surface_create_with_modifiers\0",
+                                           b"gbm_bo_get_fd_for_plane\0", b"gbm_bo_get_bpp\0"]))
+        };
+        let caps = Capabilities::new(&some, SCANOUT);
+        assert!(!caps.modifiers && !caps.planes);
+        assert!(caps.plane_fds && caps.bpp);
+
+        let all = unsafe { ffi::Optional::resolve(|_: &[u8]| capabilities as *mut c_void) };
+        let caps = Capabilities::new(&all, SCANOUT);
+        assert!(caps.modifiers && caps.modifier_flags && caps.modifier_queries && caps.planes);
+        assert!(caps.plane_fds && caps.bpp && caps.format_names);
+    }
+
+    #[test]
+    fn linear_fallback_usage() {
+        let tiled = 0x0100_0000_0000_0001;
+        let unsupported = || Err(Error::Unsupported("gbm_bo_create_with_modifiers"));
+
+        // The fallback is created, and recorded, with the LINEAR flag.
+        let (usage, recorded) = linear_fallback(&[tiled, Modifier::LINEAR.0], SCANOUT, unsupported, Ok).unwrap();
+        assert_eq!(usage, SCANOUT | LINEAR);
+        assert_eq!(recorded, SCANOUT | LINEAR);
+
+        // Without LINEAR among the modifiers there is nothing to fall back to.
+        match linear_fallback(&[tiled], SCANOUT, unsupported, Ok) {
+            Err(Error::Unsupported(_)) => (),
+            _ => panic!("fell back to a layout the modifiers do not allow")
+        }
+
+        // Other errors, and successes, are passed on with the given usage.
+        let (value, recorded) = linear_fallback(&[Modifier::LINEAR.0], SCANOUT, || Ok(1), |_| Ok(2)).unwrap();
+        assert_eq!((value, recorded), (1, SCANOUT));
+        match linear_fallback(&[Modifier::LINEAR.0], SCANOUT, || Err(Error::InvalidSize((0, 0))), |_| Ok(2)) {
+            Err(Error::InvalidSize(_)) => (),
+            _ => panic!("fell back after an allocation failure")
+        }
+    }
 }
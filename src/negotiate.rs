@@ -0,0 +1,119 @@
+//! Negotiation of a format and modifiers between buffer consumers.
+//!
+//! Every consumer of a buffer, such as a KMS plane, an EGL or Vulkan
+//! context or a Wayland client, supports its own set of formats and
+//! modifiers. A `Negotiator` finds the pairs supported by all of them and
+//! ranks them: compressed layouts before tiled ones, tiled before linear,
+//! and for primary planes, formats without alpha first.
+//!
+//! The resulting `Choice` can be passed to `Device::buffer_with_modifiers`
+//! or `Device::surface_with_modifiers`, which let the driver pick one of
+//! the modifiers.
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+use super::{Format, Layout, Modifier};
+
+/// A format with the modifiers every consumer supports for it, best first.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Choice {
+    /// The format to allocate with.
+    pub format: Format,
+    /// The modifiers to allocate with.
+    pub modifiers: Vec<Modifier>
+}
+
+impl Choice {
+    /// Returns the best layout among the modifiers.
+    pub fn layout(&self) -> Layout {
+        self.modifiers.iter().map(Modifier::layout).max().unwrap_or(Layout::Implicit)
+    }
+}
+
+/// Collects the formats and modifiers of several consumers and picks the
+/// best common choice.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Negotiator {
+    consumers: Vec<Vec<(Format, Modifier)>>,
+    formats: Vec<Format>,
+    primary: bool
+}
+
+impl Negotiator {
+    /// Creates a `Negotiator` without consumers.
+    pub fn new() -> Negotiator {
+        Negotiator::default()
+    }
+
+    /// Adds a consumer supporting the given format and modifier pairs.
+    ///
+    /// `Modifier::INVALID` stands for buffers allocated without an explicit
+    /// modifier, and only matches consumers that list it as well.
+    pub fn consumer<I>(mut self, formats: I) -> Negotiator where I: IntoIterator<Item = (Format, Modifier)> {
+        self.consumers.push(formats.into_iter().collect());
+        self
+    }
+
+    /// Only considers the given formats, preferring them in order over any
+    /// difference in layout.
+    pub fn formats(mut self, formats: &[Format]) -> Negotiator {
+        self.formats = formats.to_vec();
+        self
+    }
+
+    /// Ranks formats without alpha first, as the buffer is scanned out on a
+    /// primary plane, where nothing shows through.
+    pub fn primary_plane(mut self) -> Negotiator {
+        self.primary = true;
+        self
+    }
+
+    /// Returns every format supported by all consumers, best first.
+    ///
+    /// Formats come in the order of the first consumer when nothing else
+    /// sets them apart. Without consumers, nothing is returned.
+    pub fn choices(&self) -> Vec<Choice> {
+        let (first, others) = match self.consumers.split_first() {
+            Some(split) => split,
+            None => return Vec::new()
+        };
+        let others: Vec<HashSet<(Format, Modifier)>> = others.iter()
+            .map(|consumer| consumer.iter().cloned().collect())
+            .collect();
+
+        let mut choices: Vec<Choice> = Vec::new();
+        for &(format, modifier) in first {
+            if !self.formats.is_empty() && !self.formats.contains(&format) {
+                continue;
+            }
+            if !others.iter().all(|consumer| consumer.contains(&(format, modifier))) {
+                continue;
+            }
+
+            match choices.iter().position(|choice| choice.format == format) {
+                Some(index) => {
+                    if !choices[index].modifiers.contains(&modifier) {
+                        choices[index].modifiers.push(modifier);
+                    }
+                },
+                None => choices.push(Choice { format: format, modifiers: vec![modifier] })
+            }
+        }
+
+        for choice in &mut choices {
+            choice.modifiers.sort_by_key(|modifier| Reverse(modifier.layout()));
+        }
+        choices.sort_by_key(|choice| {
+            let alpha = self.primary && choice.format.has_alpha();
+            let preference = self.formats.iter().position(|&format| format == choice.format);
+            (alpha, preference, Reverse(choice.layout()))
+        });
+        choices
+    }
+
+    /// Returns the best format supported by all consumers, if there is one.
+    pub fn negotiate(&self) -> Option<Choice> {
+        self.choices().into_iter().next()
+    }
+}
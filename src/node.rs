@@ -0,0 +1,338 @@
+//! Enumeration of DRM device nodes.
+//!
+//! Devices are found through `/sys/class/drm`, where every primary node
+//! (`cardN`) and render node (`renderDN`) links back to the device that
+//! provides it. Nodes belonging to the same device are grouped together.
+//!
+//! A `Selector` picks one of the enumerated devices for a task, such as the
+//! render node of a discrete GPU for offscreen work, or the boot VGA device
+//! for display.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use error::Result;
+
+/// The default location of sysfs.
+pub const SYSFS_ROOT: &'static str = "/sys";
+
+/// The directory holding the DRM device files.
+pub const DEV_ROOT: &'static str = "/dev/dri";
+
+/// The environment variable read by `Selector::with_env`.
+pub const DEVICE_ENV: &'static str = "GBM_DEVICE";
+
+/// Whether a GPU is integrated into the CPU package or a separate device.
+///
+/// The kernel does not report this, so it is guessed from where the device
+/// sits: platform devices and PCI devices on the root bus, as Intel GPUs
+/// are, count as integrated, and other PCI devices as discrete. Some
+/// integrated GPUs, such as AMD APUs, sit behind a bridge and are reported
+/// as discrete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GpuKind {
+    Integrated,
+    Discrete,
+    Unknown
+}
+
+/// A DRM device and the nodes it provides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrmNode {
+    /// The path of the primary node, such as `/dev/dri/card0`.
+    pub primary: Option<PathBuf>,
+    /// The path of the render node, such as `/dev/dri/renderD128`.
+    pub render: Option<PathBuf>,
+    /// The name of the kernel driver, such as `i915` or `amdgpu`.
+    pub driver: Option<String>,
+    /// The PCI vendor ID, if the device is on the PCI bus.
+    pub vendor_id: Option<u16>,
+    /// The PCI device ID, if the device is on the PCI bus.
+    pub device_id: Option<u16>,
+    /// True if the firmware used this device for the boot console.
+    pub boot_vga: bool,
+    /// Whether the GPU is integrated or discrete.
+    pub kind: GpuKind
+}
+
+impl DrmNode {
+    /// Returns the render node if there is one, otherwise the primary node.
+    pub fn path(&self) -> Option<&Path> {
+        self.render.as_deref().or(self.primary.as_deref())
+    }
+}
+
+/// Enumerates the DRM devices of the system.
+pub fn enumerate() -> Result<Vec<DrmNode>> {
+    enumerate_in(Path::new(SYSFS_ROOT))
+}
+
+/// Enumerates the DRM devices found in the sysfs tree at `root`.
+///
+/// The paths of the returned nodes always point into `/dev/dri`, so a fake
+/// sysfs tree can be used for testing.
+pub fn enumerate_in(root: &Path) -> Result<Vec<DrmNode>> {
+    let class = root.join("class/drm");
+    let mut names = Vec::new();
+    for entry in try!(fs::read_dir(&class)) {
+        let name = try!(entry).file_name().to_string_lossy().into_owned();
+        if let Some(kind) = node_kind(&name) {
+            names.push((kind, name));
+        }
+    }
+    names.sort_by_key(|(_, name)| node_number(name));
+
+    let mut devices: Vec<(PathBuf, DrmNode)> = Vec::new();
+    for (kind, name) in names {
+        let device = match fs::canonicalize(class.join(&name).join("device")) {
+            Ok(device) => device,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into())
+        };
+
+        let index = match devices.iter().position(|(path, _)| *path == device) {
+            Some(index) => index,
+            None => {
+                let node = read_device(&device);
+                devices.push((device, node));
+                devices.len() - 1
+            }
+        };
+
+        let path = Some(Path::new(DEV_ROOT).join(&name));
+        match kind {
+            NodeKind::Primary => devices[index].1.primary = path,
+            NodeKind::Render => devices[index].1.render = path
+        }
+    }
+
+    Ok(devices.into_iter().map(|(_, node)| node).collect())
+}
+
+/// Returns the render node of the device providing a primary node.
+pub fn render_node_for(primary: &Path) -> Result<Option<PathBuf>> {
+    render_node_for_in(Path::new(SYSFS_ROOT), primary)
+}
+
+/// Returns the render node of the device providing a primary node, using
+/// the sysfs tree at `root`.
+pub fn render_node_for_in(root: &Path, primary: &Path) -> Result<Option<PathBuf>> {
+    let name = primary.file_name();
+    let nodes = try!(enumerate_in(root));
+    let node = nodes.into_iter().find(|node| {
+        node.primary.as_ref().map(|p| p.file_name()) == Some(name)
+    });
+    Ok(node.and_then(|node| node.render))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    Primary,
+    Render
+}
+
+/// Returns the kind of node for names like `card0` and `renderD128`, and
+/// `None` for connectors and anything else in the class directory.
+fn node_kind(name: &str) -> Option<NodeKind> {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if name.starts_with("card") && is_number(&name[4..]) {
+        Some(NodeKind::Primary)
+    } else if name.starts_with("renderD") && is_number(&name[7..]) {
+        Some(NodeKind::Render)
+    } else {
+        None
+    }
+}
+
+/// Returns the number of a node, so `card10` sorts after `card9`.
+fn node_number(name: &str) -> u32 {
+    let digits = name.trim_start_matches(|c: char| !c.is_ascii_digit());
+    digits.parse().unwrap_or(0)
+}
+
+/// Reads the properties of a device directory. Missing files are not
+/// errors, as only PCI devices provide IDs and `boot_vga`.
+fn read_device(device: &Path) -> DrmNode {
+    let read = |file: &str| fs::read_to_string(device.join(file)).ok().map(|s| s.trim().to_owned());
+    let hex = |file: &str| read(file).and_then(|s| u16::from_str_radix(s.trim_start_matches("0x"), 16).ok());
+
+    let driver = fs::read_link(device.join("driver")).ok()
+        .and_then(|link| link.file_name().map(|name| name.to_string_lossy().into_owned()));
+
+    DrmNode {
+        primary: None,
+        render: None,
+        driver: driver,
+        vendor_id: hex("vendor"),
+        device_id: hex("device"),
+        boot_vga: read("boot_vga").as_deref() == Some("1"),
+        kind: gpu_kind(device)
+    }
+}
+
+/// Guesses the kind of GPU from its device path, see `GpuKind`.
+fn gpu_kind(device: &Path) -> GpuKind {
+    // PCI device directories are named after their address, domain:bus:slot.function.
+    let name = device.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let parts: Vec<&str> = name.split(':').collect();
+    if parts.len() == 3 && parts[2].contains('.') {
+        return match u8::from_str_radix(parts[1], 16) {
+            Ok(0) => GpuKind::Integrated,
+            Ok(_) => GpuKind::Discrete,
+            Err(_) => GpuKind::Unknown
+        };
+    }
+
+    if device.components().any(|c| c.as_os_str() == "platform") {
+        GpuKind::Integrated
+    } else {
+        GpuKind::Unknown
+    }
+}
+
+/// What a device is selected for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    /// Allocating and rendering offscreen, which prefers render nodes.
+    Offscreen,
+    /// Driving displays, which needs a primary node and prefers the boot
+    /// VGA device.
+    Display
+}
+
+/// A device picked by a `Selector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection<'n> {
+    /// The selected device.
+    pub node: &'n DrmNode,
+    /// The node of the device to open for the purpose.
+    pub path: &'n Path
+}
+
+/// A policy for picking a device for a task.
+///
+/// A driver name or path makes the selection strict: only a matching device
+/// is picked. The purpose and GPU kind only rank the remaining devices, with
+/// ties going to the device enumerated first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector {
+    purpose: Purpose,
+    kind: Option<GpuKind>,
+    driver: Option<String>,
+    path: Option<PathBuf>
+}
+
+impl Selector {
+    /// Creates a `Selector` for the given purpose.
+    pub fn new(purpose: Purpose) -> Selector {
+        Selector {
+            purpose: purpose,
+            kind: None,
+            driver: None,
+            path: None
+        }
+    }
+
+    /// Prefers devices of the given kind.
+    pub fn prefer(mut self, kind: GpuKind) -> Selector {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Only selects devices using the given kernel driver.
+    pub fn driver<S>(mut self, driver: S) -> Selector where S: Into<String> {
+        self.driver = Some(driver.into());
+        self
+    }
+
+    /// Only selects the device providing the given node.
+    pub fn path<P>(mut self, path: P) -> Selector where P: Into<PathBuf> {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Applies a user override from the `GBM_DEVICE` environment variable,
+    /// see `with_override`.
+    pub fn with_env(self) -> Selector {
+        self.with_var(DEVICE_ENV)
+    }
+
+    /// Applies a user override from the given environment variable, see
+    /// `with_override`.
+    pub fn with_var(self, var: &str) -> Selector {
+        match env::var(var) {
+            Ok(value) => self.with_override(&value),
+            Err(_) => self
+        }
+    }
+
+    /// Applies a user override: an absolute path selects the device
+    /// providing that node, anything else is a driver name. Empty values
+    /// are ignored.
+    pub fn with_override(self, value: &str) -> Selector {
+        let value = value.trim();
+        if value.is_empty() {
+            self
+        } else if value.starts_with('/') {
+            self.path(value)
+        } else {
+            self.driver(value)
+        }
+    }
+
+    /// Picks a device from enumerated nodes.
+    pub fn select<'n>(&self, nodes: &'n [DrmNode]) -> Option<Selection<'n>> {
+        let mut best: Option<(Selection<'n>, (bool, bool, bool))> = None;
+        for node in nodes {
+            if !self.matches(node) {
+                continue;
+            }
+
+            let path = match self.purpose {
+                Purpose::Offscreen => node.path(),
+                Purpose::Display => node.primary.as_deref()
+            };
+            let path = match path {
+                Some(path) => path,
+                None => continue
+            };
+
+            let rank = (
+                self.kind.is_none_or(|kind| node.kind == kind),
+                self.purpose == Purpose::Offscreen || node.boot_vga,
+                self.purpose == Purpose::Display || node.render.is_some()
+            );
+            if best.as_ref().is_none_or(|&(_, best)| rank > best) {
+                best = Some((Selection { node: node, path: path }, rank));
+            }
+        }
+
+        best.map(|(selection, _)| selection)
+    }
+
+    /// Enumerates the devices of the system and picks one, returning the
+    /// path of the node to open.
+    pub fn select_device(&self) -> Result<Option<PathBuf>> {
+        let nodes = try!(enumerate());
+        Ok(self.select(&nodes).map(|selection| selection.path.to_owned()))
+    }
+
+    fn matches(&self, node: &DrmNode) -> bool {
+        if let Some(ref driver) = self.driver {
+            if node.driver.as_ref() != Some(driver) {
+                return false;
+            }
+        }
+
+        if let Some(ref path) = self.path {
+            let path = Some(path);
+            if node.primary.as_ref() != path && node.render.as_ref() != path {
+                return false;
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,186 @@
+//! Sharing buffers between devices with PRIME.
+//!
+//! With hybrid graphics, buffers are often rendered on one `Device` and
+//! scanned out on another. The buffer is exported as dma-bufs and imported
+//! into the other device with its modifier when that device supports it.
+//! Otherwise it is copied into a staging buffer on the rendering device,
+//! using a modifier both devices support or a linear layout, which every
+//! device can import. The staging buffer is kept so that later frames can
+//! be copied into it with `Shared::update`.
+
+use std::fs::File;
+use std::os::unix::io::{AsFd, BorrowedFd};
+
+use super::{Device, Buffer, BufferFlags, DmabufDescriptor, Format, Modifier, LINEAR, RENDERING, TRANSFER_READ, TRANSFER_WRITE};
+use error::{Result, Error};
+use negotiate::Negotiator;
+
+/// How a buffer was shared with another device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharePath {
+    /// The buffer was imported as it is, using the given modifier.
+    Direct(Modifier),
+    /// The other device could not use the buffer's layout, so it was copied
+    /// into a staging buffer with the given modifier, which was imported
+    /// instead. The modifier is `Modifier::LINEAR` if the devices had none
+    /// in common.
+    Copy(Modifier)
+}
+
+/// A buffer shared with another device, and how it got there.
+pub struct Shared<'s, 'd, F, G> where F: 's + AsRef<File>, G: 'd + AsRef<File> {
+    /// The buffer on the other device.
+    pub buffer: Buffer<'d, G>,
+    /// Whether the buffer was imported directly or copied.
+    pub path: SharePath,
+    /// The buffer on the source device that `buffer` was imported from,
+    /// when the contents had to be copied.
+    staging: Option<Buffer<'s, F>>
+}
+
+impl<'s, 'd, F, G> Shared<'s, 'd, F, G> where F: AsRef<File>, G: AsRef<File> {
+    /// Makes the new contents of the source buffer visible on the other
+    /// device, which should be called after every frame rendered to it.
+    ///
+    /// Directly imported buffers share their memory, so nothing is done.
+    /// Copied buffers have the source buffer copied into their staging
+    /// buffer again.
+    pub fn update(&mut self, source: &mut Buffer<F>) -> Result<()> {
+        match self.staging {
+            Some(ref mut staging) => copy(source, staging),
+            None => Ok(())
+        }
+    }
+}
+
+/// Shares a buffer created on `source` with `target`.
+///
+/// The buffer is imported directly when `target` supports its format and
+/// modifier. If it does not, or the import fails, the buffer is copied into
+/// a staging buffer on `source` that is imported instead. The staging
+/// buffer uses the best of `modifiers`, the modifiers `source` can allocate
+/// with, that `target` supports, or a linear layout if there is none.
+/// Copying needs a format with a single plane.
+pub fn share<'s, 'd, F, G>(source: &'s Device<F>, buffer: &mut Buffer<F>, modifiers: &[Modifier], target: &'d Device<G>, flags: BufferFlags) -> Result<Shared<'s, 'd, F, G>>
+    where F: AsRef<File>, G: AsRef<File>
+{
+    let mut descriptor = try!(buffer.descriptor());
+    descriptor.usage = flags;
+    let (format, modifier) = (descriptor.format, descriptor.modifier);
+
+    // Without the query, the import itself tells whether the layout is usable.
+    let supported = target.is_modifier_supported(format, modifier).unwrap_or(true);
+    if supported {
+        if let Ok(imported) = import(buffer, &descriptor, target) {
+            return Ok(Shared { buffer: imported, path: SharePath::Direct(modifier), staging: None });
+        }
+    }
+
+    let common = common_modifiers(format, modifiers, |modifier| target.is_modifier_supported(format, modifier));
+    if !common.is_empty() {
+        if let Ok(staging) = source.buffer_with_modifiers(buffer.size(), format, &common, RENDERING) {
+            if let Ok(shared) = share_copy(buffer, staging, target, flags) {
+                return Ok(shared);
+            }
+        }
+    }
+
+    let staging = try!(source.buffer(buffer.size(), format, LINEAR));
+    share_copy(buffer, staging, target, flags)
+}
+
+/// Returns the modifiers `target_supports` accepts for a format, best
+/// first. Modifiers the target cannot be asked about are left out.
+fn common_modifiers<S>(format: Format, modifiers: &[Modifier], mut target_supports: S) -> Vec<Modifier>
+    where S: FnMut(Modifier) -> Result<bool>
+{
+    let common: Vec<(Format, Modifier)> = modifiers.iter().cloned()
+        .filter(|&modifier| modifier != Modifier::INVALID)
+        .filter(|&modifier| target_supports(modifier).unwrap_or(false))
+        .map(|modifier| (format, modifier))
+        .collect();
+    Negotiator::new().consumer(common).negotiate().map(|choice| choice.modifiers).unwrap_or_default()
+}
+
+/// Copies `buffer` into `staging` and imports `staging` into `target`.
+fn share_copy<'s, 'd, F, G>(buffer: &mut Buffer<F>, mut staging: Buffer<'s, F>, target: &'d Device<G>, flags: BufferFlags) -> Result<Shared<'s, 'd, F, G>>
+    where F: AsRef<File>, G: AsRef<File>
+{
+    try!(copy(buffer, &mut staging));
+    let mut descriptor = try!(staging.descriptor());
+    // Buffers allocated with the LINEAR flag may not report their modifier.
+    if descriptor.modifier == Modifier::INVALID && staging.usage().contains(LINEAR) {
+        descriptor.modifier = Modifier::LINEAR;
+    }
+    descriptor.usage = flags;
+    let imported = try!(import(&staging, &descriptor, target));
+    Ok(Shared { buffer: imported, path: SharePath::Copy(descriptor.modifier), staging: Some(staging) })
+}
+
+/// Exports every plane of `buffer` and imports them into `target` as
+/// described.
+fn import<'d, F, G>(buffer: &Buffer<F>, descriptor: &DmabufDescriptor, target: &'d Device<G>) -> Result<Buffer<'d, G>>
+    where F: AsRef<File>, G: AsRef<File>
+{
+    let fds = try!(buffer.plane_fds());
+    let borrowed: Vec<BorrowedFd> = fds.iter().map(|fd| fd.as_fd()).collect();
+    target.import_dmabuf(descriptor, &borrowed)
+}
+
+/// Copies the pixels of one single-plane buffer into another of the same
+/// size and format.
+fn copy<F>(from: &mut Buffer<F>, to: &mut Buffer<F>) -> Result<()> where F: AsRef<File> {
+    let code = from.format();
+    let format = try!(Format::from_fourcc(code).ok_or(Error::UnknownFormat(code)));
+    let cpp = try!(format.bytes_per_pixel().ok_or(Error::UnsupportedFormat(format)));
+    let (width, height) = from.size();
+
+    let src = try!(from.map(TRANSFER_READ));
+    let mut dst = try!(to.map(TRANSFER_WRITE));
+    let (src_stride, dst_stride) = (src.stride(), dst.stride());
+    copy_rows(&src, src_stride, &mut dst, dst_stride, width * cpp, height);
+    Ok(())
+}
+
+/// Copies `height` rows of `row` bytes between memory with different
+/// strides.
+fn copy_rows(src: &[u8], src_stride: u32, dst: &mut [u8], dst_stride: u32, row: u32, height: u32) {
+    let (src_stride, dst_stride, row) = (src_stride as usize, dst_stride as usize, row as usize);
+    for y in 0..height as usize {
+        let (src_start, dst_start) = (y * src_stride, y * dst_stride);
+        dst[dst_start..dst_start + row].copy_from_slice(&src[src_start..src_start + row]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{common_modifiers, copy_rows};
+    use {Format, Modifier};
+    use error::Error;
+
+    #[test]
+    fn negotiated_modifiers() {
+        let tiled = Modifier(0x0100_0000_0000_0001);
+        let compressed = Modifier(0x0100_0000_0000_0006);
+        let other = Modifier(0x0200_0000_0000_0001);
+        let offered = [Modifier::LINEAR, tiled, Modifier::INVALID, compressed, other];
+
+        // Only modifiers the target supports are kept, best layout first.
+        let common = common_modifiers(Format::XRGB8888, &offered, |modifier| Ok(modifier != other));
+        assert_eq!(common, vec![compressed, tiled, Modifier::LINEAR]);
+
+        let common = common_modifiers(Format::XRGB8888, &offered, |_| Err(Error::Unsupported("query")));
+        assert!(common.is_empty());
+        assert!(common_modifiers(Format::XRGB8888, &[], |_| Ok(true)).is_empty());
+    }
+
+    #[test]
+    fn copied_rows() {
+        let src: Vec<u8> = (0..32).collect();
+        let mut dst = vec![0xff; 3 * 12];
+        copy_rows(&src, 8, &mut dst, 12, 6, 3);
+        assert_eq!(&dst[0..12], &[0, 1, 2, 3, 4, 5, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(&dst[12..18], &[8, 9, 10, 11, 12, 13]);
+        assert_eq!(&dst[24..30], &[16, 17, 18, 19, 20, 21]);
+    }
+}
@@ -0,0 +1,426 @@
+//! A software implementation of the `Device` API.
+//!
+//! Buffers are linear and backed by memfds, so they can be mapped, shared
+//! and exported as real file descriptors without a GPU. This allows
+//! applications and tests to exercise allocation, mapping, user data and
+//! surface locking on machines without libgbm or a DRM device.
+
+use errno::Errno;
+use libc;
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::raw::c_void;
+use std::ptr::null_mut;
+use std::rc::Rc;
+use std::slice;
+
+use super::{Allocator, BufferObject, BufferFlags, DmabufDescriptor, Format, Modifier, TransferFlags, WRITE};
+use error::{Result, Error};
+
+/// The alignment of every buffer's stride, in bytes.
+const STRIDE_ALIGNMENT: u32 = 64;
+
+/// The number of buffers in every `Surface`.
+pub const SURFACE_BUFFERS: usize = 4;
+
+/// A `Device` allocating buffers in system memory.
+pub struct Device {
+    _private: ()
+}
+
+impl<'a> Device {
+    /// Creates a software `Device`.
+    pub fn new() -> Device {
+        Device {
+            _private: ()
+        }
+    }
+
+    /// Creates a `Buffer` using the given size and parameters.
+    pub fn buffer(&'a self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Buffer<'a>> {
+        let storage = try!(Storage::new(size, format));
+        Ok(Buffer::new(Rc::new(storage), flags, None))
+    }
+
+    /// Imports a dma-buf as a `Buffer`, using the layout and usage of a
+    /// descriptor with a single linear plane.
+    ///
+    /// The file descriptor is duplicated, so it may be closed afterwards.
+    pub fn import_dmabuf(&'a self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Buffer<'a>> {
+        let (fd, stride) = try!(super::linear_plane(descriptor, fds));
+        let fd = try!(fd.try_clone_to_owned());
+        let storage = try!(Storage::from_fd(fd, descriptor.size(), stride, descriptor.format));
+        Ok(Buffer::new(Rc::new(storage), descriptor.usage, None))
+    }
+
+    /// Creates a `Surface` using the given size and parameters.
+    pub fn surface(&'a self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Surface> {
+        Surface::from_device(self, size, format, flags)
+    }
+}
+
+impl Default for Device {
+    fn default() -> Device {
+        Device::new()
+    }
+}
+
+/// The memory behind a `Buffer`, mapped for as long as it exists.
+struct Storage {
+    fd: OwnedFd,
+    data: *mut u8,
+    size: (u32, u32),
+    stride: u32,
+    format: Format,
+    user_data: RefCell<Option<Rc<dyn Any>>>
+}
+
+/// Returns the stride of rows of `width` pixels, aligned to
+/// `STRIDE_ALIGNMENT`, or `None` if it does not fit in a `u32`.
+fn aligned_stride(width: u32, cpp: u32) -> Option<u32> {
+    width.checked_mul(cpp).and_then(|row| row.div_ceil(STRIDE_ALIGNMENT).checked_mul(STRIDE_ALIGNMENT))
+}
+
+/// Returns the length of `height` rows of `stride` bytes, or `None` if it
+/// does not fit in memory.
+fn buffer_len(stride: u32, height: u32) -> Option<usize> {
+    (stride as usize).checked_mul(height as usize)
+}
+
+impl Storage {
+    fn new(size: (u32, u32), format: Format) -> Result<Storage> {
+        let cpp = try!(format.bytes_per_pixel().ok_or(Error::UnsupportedFormat(format)));
+        let stride = try!(aligned_stride(size.0, cpp).ok_or(Error::InvalidSize(size)));
+        let len = try!(buffer_len(stride, size.1).ok_or(Error::InvalidSize(size)));
+
+        let fd = unsafe {
+            let fd = libc::memfd_create(b"gbm-software\0".as_ptr() as *const _, libc::MFD_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Storage::from_fd(fd, size, stride, format)
+    }
+
+    fn from_fd(fd: OwnedFd, size: (u32, u32), stride: u32, format: Format) -> Result<Storage> {
+        let len = try!(buffer_len(stride, size.1).ok_or(Error::InvalidSize(size)));
+        let available = try!(super::file_len(fd.as_fd()));
+        if available < len as u64 {
+            return Err(Error::InvalidLength { expected: len, actual: available as usize });
+        }
+
+        let data = unsafe {
+            libc::mmap(null_mut(), len.max(1), libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED, fd.as_raw_fd(), 0)
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        let storage = Storage {
+            fd: fd,
+            data: data as *mut u8,
+            size: size,
+            stride: stride,
+            format: format,
+            user_data: RefCell::new(None)
+        };
+        Ok(storage)
+    }
+
+    fn len(&self) -> usize {
+        self.stride as usize * self.size.1 as usize
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.data as *mut c_void, self.len().max(1)) };
+    }
+}
+
+/// A linear buffer in system memory.
+pub struct Buffer<'a> {
+    storage: Rc<Storage>,
+    usage: BufferFlags,
+    surface: Option<(&'a Surface, usize)>
+}
+
+impl<'a> Buffer<'a> {
+    fn new(storage: Rc<Storage>, usage: BufferFlags, surface: Option<(&'a Surface, usize)>) -> Buffer<'a> {
+        Buffer {
+            storage: storage,
+            usage: usage,
+            surface: surface
+        }
+    }
+
+    /// Returns the width and height of the buffer.
+    pub fn size(&self) -> (u32, u32) {
+        self.storage.size
+    }
+
+    /// Returns the stride of the buffer.
+    pub fn stride(&self) -> u32 {
+        self.storage.stride
+    }
+
+    /// Returns the format of the buffer.
+    pub fn format(&self) -> u32 {
+        self.storage.format as u32
+    }
+
+    /// Returns the usage flags the buffer was created with.
+    pub fn usage(&self) -> BufferFlags {
+        self.usage
+    }
+
+    /// Returns the format modifier of the buffer, which is always linear.
+    pub fn modifier(&self) -> Result<Modifier> {
+        Ok(Modifier::LINEAR)
+    }
+
+    /// Returns the number of planes in the buffer, which is always one.
+    pub fn plane_count(&self) -> Result<u32> {
+        Ok(1)
+    }
+
+    /// Describes the buffer for importing its dma-buf elsewhere.
+    pub fn descriptor(&self) -> DmabufDescriptor {
+        DmabufDescriptor::linear(self.size(), self.storage.format, self.stride(), self.usage)
+    }
+
+    /// Exports the buffer as a file descriptor.
+    pub fn fd(&self) -> Result<OwnedFd> {
+        Ok(try!(self.storage.fd.try_clone()))
+    }
+
+    /// Writes data directly into the buffer.
+    ///
+    /// The buffer must have been created with the `WRITE` usage flag, and
+    /// `data` must cover the whole buffer, that is `height * stride` bytes.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if !self.usage.contains(WRITE) {
+            return Err(Error::MissingUsage(WRITE));
+        }
+
+        let expected = self.storage.len();
+        if data.len() != expected {
+            return Err(Error::InvalidLength { expected: expected, actual: data.len() });
+        }
+
+        unsafe { slice::from_raw_parts_mut(self.storage.data, expected) }.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Writes tightly packed rows of 4-byte pixels into the buffer, see
+    /// `gbm::Buffer::write_packed`.
+    pub fn write_packed(&mut self, data: &[u8]) -> Result<()> {
+        let padded = try!(super::pad_rows(self.format(), self.size(), self.stride(), data));
+        self.write(&padded)
+    }
+
+    /// Maps the whole buffer into memory for CPU access.
+    pub fn map(&mut self, _flags: TransferFlags) -> Result<Mapping> {
+        let mapping = Mapping {
+            data: unsafe { slice::from_raw_parts_mut(self.storage.data, self.storage.len()) },
+            stride: self.storage.stride
+        };
+        Ok(mapping)
+    }
+
+    /// Attach a reference counted object to the buffer. This can be
+    /// retrieved again using `get_user_data`
+    ///
+    /// Buffers of a `Surface` keep their user data between locks.
+    pub fn set_user_data<D>(&self, data: Option<Rc<D>>) where D: 'static {
+        *self.storage.user_data.borrow_mut() = data.map(|d| d as Rc<dyn Any>);
+    }
+
+    /// Retrieves the reference counted data set using `set_user_data`.
+    ///
+    /// # Safety
+    /// Unlike the libgbm backend, the type is checked and `None` is returned
+    /// if it does not match. The method is kept unsafe so that code can be
+    /// moved between backends unchanged.
+    pub unsafe fn get_user_data<D>(&self) -> Option<Rc<D>> where D: 'static {
+        let data = self.storage.user_data.borrow().clone();
+        data.and_then(|d| d.downcast::<D>().ok())
+    }
+}
+
+impl<'a> Drop for Buffer<'a> {
+    fn drop(&mut self) {
+        if let Some((surface, index)) = self.surface {
+            surface.release(index);
+        }
+    }
+}
+
+impl<'a> BufferObject for Buffer<'a> {
+    fn size(&self) -> (u32, u32) {
+        Buffer::size(self)
+    }
+
+    fn stride(&self) -> u32 {
+        Buffer::stride(self)
+    }
+
+    fn format(&self) -> u32 {
+        Buffer::format(self)
+    }
+
+    fn descriptor(&self) -> Result<DmabufDescriptor> {
+        Ok(Buffer::descriptor(self))
+    }
+}
+
+/// A CPU mapping of a software `Buffer`.
+pub struct Mapping<'b> {
+    data: &'b mut [u8],
+    stride: u32
+}
+
+impl<'b> Mapping<'b> {
+    /// Returns the stride of the mapped memory.
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+}
+
+impl<'b> Deref for Mapping<'b> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'b> DerefMut for Mapping<'b> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    /// The buffer can be rendered to.
+    Free,
+    /// The buffer has been rendered to and waits to be locked.
+    Rendered,
+    /// The buffer is locked by the application.
+    Locked
+}
+
+/// A `Surface` with a fixed ring of buffers.
+///
+/// `render` stands in for drawing and swapping buffers with EGL, after
+/// which the rendered buffer is available from `lock_front_buffer`.
+pub struct Surface {
+    buffers: Vec<Rc<Storage>>,
+    slots: RefCell<Vec<Slot>>,
+    next: Cell<usize>,
+    usage: BufferFlags
+}
+
+impl<'a> Surface {
+    /// Creates a surface from a `Device` and the given parameters.
+    pub fn from_device(_device: &'a Device, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Surface> {
+        let mut buffers = Vec::with_capacity(SURFACE_BUFFERS);
+        for _ in 0..SURFACE_BUFFERS {
+            buffers.push(Rc::new(try!(Storage::new(size, format))));
+        }
+
+        let surface = Surface {
+            buffers: buffers,
+            slots: RefCell::new(vec![Slot::Free; SURFACE_BUFFERS]),
+            next: Cell::new(0),
+            usage: flags
+        };
+        Ok(surface)
+    }
+
+    /// Draws into the next free buffer and makes it the front buffer.
+    ///
+    /// `func` is called with the mapped memory of the buffer and its stride.
+    /// A front buffer that was never locked is dropped and reused.
+    pub fn render<R, M>(&self, func: M) -> Result<R> where M: FnOnce(&mut [u8], u32) -> R {
+        let index = {
+            let mut slots = self.slots.borrow_mut();
+            for slot in slots.iter_mut() {
+                if *slot == Slot::Rendered {
+                    *slot = Slot::Free;
+                }
+            }
+
+            let start = self.next.get();
+            let index = (0..slots.len()).map(|i| (start + i) % slots.len())
+                                        .find(|&i| slots[i] == Slot::Free);
+            let index = try!(index.ok_or(Error::Ioctl(Errno(libc::EBUSY))));
+            slots[index] = Slot::Locked;
+            index
+        };
+
+        let storage = &self.buffers[index];
+        let data = unsafe { slice::from_raw_parts_mut(storage.data, storage.len()) };
+        let result = func(data, storage.stride);
+
+        self.slots.borrow_mut()[index] = Slot::Rendered;
+        self.next.set((index + 1) % self.buffers.len());
+        Ok(result)
+    }
+
+    /// Locks the front buffer to be used for display.
+    ///
+    /// The buffer is released back to the surface when dropped. Fails if
+    /// nothing was rendered since the last lock.
+    pub fn lock_front_buffer(&'a self) -> Result<Buffer<'a>> {
+        let mut slots = self.slots.borrow_mut();
+        let index = try!(slots.iter().position(|&s| s == Slot::Rendered)
+                                     .ok_or(Error::Ioctl(Errno(libc::EINVAL))));
+        slots[index] = Slot::Locked;
+        Ok(Buffer::new(self.buffers[index].clone(), self.usage, Some((self, index))))
+    }
+
+    /// Returns true if a buffer is available to render to.
+    pub fn has_free_buffers(&self) -> bool {
+        self.slots.borrow().iter().any(|&s| s != Slot::Locked)
+    }
+
+    fn release(&self, index: usize) {
+        self.slots.borrow_mut()[index] = Slot::Free;
+    }
+}
+
+impl Allocator for Device {
+    type Buffer<'a> = Buffer<'a>;
+
+    fn create_buffer(&self, size: (u32, u32), format: Format, flags: BufferFlags) -> Result<Buffer<'_>> {
+        self.buffer(size, format, flags)
+    }
+
+    fn import_buffer(&self, descriptor: &DmabufDescriptor, fds: &[BorrowedFd]) -> Result<Buffer<'_>> {
+        self.import_dmabuf(descriptor, fds)
+    }
+
+    fn export_buffer(&self, buffer: &Buffer<'_>) -> Result<Vec<OwnedFd>> {
+        Ok(vec![try!(buffer.fd())])
+    }
+
+    fn map_buffer<R, M>(&self, buffer: &mut Buffer<'_>, func: M) -> Result<R>
+        where M: FnOnce(&mut [u8], u32) -> R
+    {
+        let mut mapping = try!(buffer.map(super::TRANSFER_READ_WRITE));
+        let stride = mapping.stride();
+        Ok(func(&mut mapping, stride))
+    }
+}
@@ -0,0 +1,155 @@
+//! Vulkan interop through dma-bufs.
+//!
+//! `ImageImport::from_buffer` describes a `Buffer` for creating a `VkImage`
+//! with `VK_EXT_image_drm_format_modifier` and binding memory imported with
+//! `VK_EXT_external_memory_dma_buf`. `import` goes the other way, turning
+//! the dma-buf of a Vulkan image into a `Buffer`.
+//!
+//! Formats without alpha map to the same `VkFormat` as their alpha variant,
+//! as Vulkan has no padding formats. The alpha channel of such images
+//! should be ignored, for example with a component swizzle.
+
+use ash::vk;
+
+use std::fs::File;
+use std::os::unix::io::{BorrowedFd, OwnedFd};
+
+use super::{Buffer, BufferFlags, Device, DmabufDescriptor, Format, Modifier, Plane};
+use error::{Result, Error};
+
+/// Returns the `VkFormat` with the same memory layout as a format.
+pub fn vk_format(format: Format) -> Option<vk::Format> {
+    let vk_format = match format {
+        Format::R8 => vk::Format::R8_UNORM,
+        Format::GR88 => vk::Format::R8G8_UNORM,
+        Format::RGB565 => vk::Format::R5G6B5_UNORM_PACK16,
+        Format::BGR565 => vk::Format::B5G6R5_UNORM_PACK16,
+        Format::RGB888 => vk::Format::B8G8R8_UNORM,
+        Format::BGR888 => vk::Format::R8G8B8_UNORM,
+        Format::XRGB8888 | Format::ARGB8888 => vk::Format::B8G8R8A8_UNORM,
+        Format::XBGR8888 | Format::ABGR8888 => vk::Format::R8G8B8A8_UNORM,
+        Format::XRGB2101010 | Format::ARGB2101010 => vk::Format::A2R10G10B10_UNORM_PACK32,
+        Format::XBGR2101010 | Format::ABGR2101010 => vk::Format::A2B10G10R10_UNORM_PACK32,
+        Format::XBGR16161616F | Format::ABGR16161616F => vk::Format::R16G16B16A16_SFLOAT,
+        Format::YUYV => vk::Format::G8B8G8R8_422_UNORM,
+        Format::UYVY => vk::Format::B8G8R8G8_422_UNORM,
+        Format::NV12 => vk::Format::G8_B8R8_2PLANE_420_UNORM,
+        Format::P010 => vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16,
+        Format::YUV420 => vk::Format::G8_B8_R8_3PLANE_420_UNORM,
+        _ => return None
+    };
+    Some(vk_format)
+}
+
+/// Returns the format with the same memory layout as a `VkFormat`.
+///
+/// Formats with four channels map to the variant with alpha.
+pub fn from_vk_format(vk_format: vk::Format) -> Option<Format> {
+    let format = match vk_format {
+        vk::Format::R8_UNORM => Format::R8,
+        vk::Format::R8G8_UNORM => Format::GR88,
+        vk::Format::R5G6B5_UNORM_PACK16 => Format::RGB565,
+        vk::Format::B5G6R5_UNORM_PACK16 => Format::BGR565,
+        vk::Format::B8G8R8_UNORM => Format::RGB888,
+        vk::Format::R8G8B8_UNORM => Format::BGR888,
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Format::ARGB8888,
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => Format::ABGR8888,
+        vk::Format::A2R10G10B10_UNORM_PACK32 => Format::ARGB2101010,
+        vk::Format::A2B10G10R10_UNORM_PACK32 => Format::ABGR2101010,
+        vk::Format::R16G16B16A16_SFLOAT => Format::ABGR16161616F,
+        vk::Format::G8B8G8R8_422_UNORM => Format::YUYV,
+        vk::Format::B8G8R8G8_422_UNORM => Format::UYVY,
+        vk::Format::G8_B8R8_2PLANE_420_UNORM => Format::NV12,
+        vk::Format::G10X6_B10X6R10X6_2PLANE_420_UNORM_3PACK16 => Format::P010,
+        vk::Format::G8_B8_R8_3PLANE_420_UNORM => Format::YUV420,
+        _ => return None
+    };
+    Some(format)
+}
+
+/// Everything needed to import a `Buffer` into Vulkan.
+#[derive(Debug)]
+pub struct ImageImport {
+    /// The format of the image.
+    pub format: vk::Format,
+    /// The size of the image.
+    pub extent: vk::Extent2D,
+    /// The format modifier of the buffer.
+    pub modifier: Modifier,
+    /// The offset and row pitch of each memory plane.
+    pub plane_layouts: Vec<vk::SubresourceLayout>,
+    /// The dma-buf of the buffer. Vulkan takes ownership of it when the
+    /// memory is imported, so pass it on with `into_raw_fd`.
+    pub fd: OwnedFd
+}
+
+impl ImageImport {
+    /// Describes a buffer, exporting a new dma-buf for it.
+    ///
+    /// The buffer must have an explicit modifier, as Vulkan cannot import
+    /// dma-bufs with an implicit layout.
+    pub fn from_buffer<F>(buffer: &Buffer<F>) -> Result<ImageImport> where F: AsRef<File> {
+        let descriptor = try!(buffer.descriptor());
+        let vk_format = try!(vk_format(descriptor.format).ok_or(Error::UnsupportedFormat(descriptor.format)));
+        if descriptor.modifier == Modifier::INVALID {
+            return Err(Error::Unsupported("explicit modifiers"));
+        }
+
+        let plane_layouts = descriptor.planes.iter().map(|plane| {
+            vk::SubresourceLayout {
+                offset: plane.offset as vk::DeviceSize,
+                size: 0,
+                row_pitch: plane.stride as vk::DeviceSize,
+                array_pitch: 0,
+                depth_pitch: 0
+            }
+        }).collect();
+
+        let import = ImageImport {
+            format: vk_format,
+            extent: vk::Extent2D { width: descriptor.width, height: descriptor.height },
+            modifier: descriptor.modifier,
+            plane_layouts: plane_layouts,
+            fd: try!(buffer.fd())
+        };
+        Ok(import)
+    }
+
+    /// Returns the structure to chain into `VkImageCreateInfo` with tiling
+    /// `DRM_FORMAT_MODIFIER_EXT`.
+    pub fn modifier_info(&self) -> vk::ImageDrmFormatModifierExplicitCreateInfoEXT<'_> {
+        vk::ImageDrmFormatModifierExplicitCreateInfoEXT::default()
+            .drm_format_modifier(self.modifier.0)
+            .plane_layouts(&self.plane_layouts)
+    }
+
+    /// Returns the structure to chain into `VkImageCreateInfo` for memory
+    /// imported from a dma-buf.
+    pub fn external_memory_info(&self) -> vk::ExternalMemoryImageCreateInfo<'static> {
+        vk::ExternalMemoryImageCreateInfo::default()
+            .handle_types(vk::ExternalMemoryHandleTypeFlags::DMA_BUF_EXT)
+    }
+}
+
+/// Imports the dma-buf of a Vulkan image as a `Buffer`.
+///
+/// The layouts are those of each memory plane, as returned by
+/// `vkGetImageSubresourceLayout`, and all planes must be in `fd`, which is
+/// only borrowed.
+pub fn import<'a, F>(device: &'a Device<F>, fd: BorrowedFd, extent: vk::Extent2D, format: vk::Format, modifier: Modifier, layouts: &[vk::SubresourceLayout], flags: BufferFlags) -> Result<Buffer<'a, F>>
+    where F: AsRef<File>
+{
+    let format = try!(from_vk_format(format).ok_or(Error::UnknownFormat(format.as_raw() as u32)));
+    let descriptor = DmabufDescriptor {
+        width: extent.width,
+        height: extent.height,
+        format: format,
+        modifier: modifier,
+        planes: layouts.iter()
+            .map(|layout| Plane { offset: layout.offset as u32, stride: layout.row_pitch as u32 })
+            .collect(),
+        usage: flags
+    };
+    let fds = vec![fd; descriptor.planes.len()];
+    device.import_dmabuf(&descriptor, &fds)
+}
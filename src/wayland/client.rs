@@ -0,0 +1,139 @@
+//! Creating `wl_buffer`s from buffers.
+//!
+//! `create_buffer_immed` returns the `wl_buffer` right away, and the
+//! compositor kills the client if the import fails. `create_buffer` asks
+//! the compositor first, which answers with a `created` or `failed` event
+//! on the returned params object.
+
+use wayland_client::{Dispatch, Proxy, QueueHandle};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_protocols::wp::linux_dmabuf::zv1::client::zwp_linux_buffer_params_v1::{Flags, ZwpLinuxBufferParamsV1};
+use wayland_protocols::wp::linux_dmabuf::zv1::client::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1;
+
+use std::fs::File;
+use std::os::unix::io::AsFd;
+
+use super::super::{Buffer, DmabufDescriptor};
+use error::{Result, Error};
+
+/// The arguments of a `zwp_linux_buffer_params_v1.add` request, apart from
+/// the file descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PlaneArgs {
+    index: u32,
+    offset: u32,
+    stride: u32,
+    modifier_hi: u32,
+    modifier_lo: u32
+}
+
+/// Returns the `add` arguments of every plane of a descriptor.
+fn plane_args(descriptor: &DmabufDescriptor) -> Vec<PlaneArgs> {
+    let modifier = descriptor.modifier.0;
+    descriptor.planes.iter().enumerate().map(|(index, plane)| PlaneArgs {
+        index: index as u32,
+        offset: plane.offset,
+        stride: plane.stride,
+        modifier_hi: (modifier >> 32) as u32,
+        modifier_lo: modifier as u32
+    }).collect()
+}
+
+/// Returns the width and height of a buffer as the `create` requests take
+/// them.
+fn create_size(size: (u32, u32)) -> Result<(i32, i32)> {
+    if size.0 > i32::MAX as u32 || size.1 > i32::MAX as u32 {
+        return Err(Error::InvalidSize(size));
+    }
+    Ok((size.0 as i32, size.1 as i32))
+}
+
+/// Adds every plane of a buffer to a params object.
+///
+/// Buffers without an explicit modifier are sent with `Modifier::INVALID`,
+/// leaving the layout to the driver.
+pub fn add_planes<F>(params: &ZwpLinuxBufferParamsV1, buffer: &Buffer<F>) -> Result<()> where F: AsRef<File> {
+    let descriptor = try!(buffer.descriptor());
+    // The descriptors are duplicated when the requests are queued.
+    let fds = try!(buffer.plane_fds());
+    for (args, fd) in plane_args(&descriptor).into_iter().zip(&fds) {
+        params.add(fd.as_fd(), args.index, args.offset, args.stride, args.modifier_hi, args.modifier_lo);
+    }
+    Ok(())
+}
+
+/// Creates a `wl_buffer` for a buffer immediately, which needs version 2 of
+/// `zwp_linux_dmabuf_v1`.
+pub fn create_buffer_immed<F, D, U>(dmabuf: &ZwpLinuxDmabufV1, buffer: &Buffer<F>, qh: &QueueHandle<D>, udata: U) -> Result<WlBuffer>
+    where F: AsRef<File>,
+          D: Dispatch<ZwpLinuxBufferParamsV1, ()> + Dispatch<WlBuffer, U> + 'static,
+          U: Send + Sync + 'static
+{
+    if dmabuf.version() < 2 {
+        return Err(Error::Unsupported("zwp_linux_buffer_params_v1.create_immed"));
+    }
+
+    let (width, height) = try!(create_size(buffer.size()));
+    let params = dmabuf.create_params(qh, ());
+    let result = add_planes(&params, buffer).map(|()| {
+        params.create_immed(width, height, buffer.format(), Flags::empty(), qh, udata)
+    });
+    params.destroy();
+    result
+}
+
+/// Asks the compositor to create a `wl_buffer` for a buffer.
+///
+/// The compositor answers with a `created` event carrying the `wl_buffer`,
+/// or a `failed` event, on the returned params object, which should then
+/// be destroyed.
+pub fn create_buffer<F, D, U>(dmabuf: &ZwpLinuxDmabufV1, buffer: &Buffer<F>, qh: &QueueHandle<D>, udata: U) -> Result<ZwpLinuxBufferParamsV1>
+    where F: AsRef<File>,
+          D: Dispatch<ZwpLinuxBufferParamsV1, U> + 'static,
+          U: Send + Sync + 'static
+{
+    let (width, height) = try!(create_size(buffer.size()));
+    let params = dmabuf.create_params(qh, udata);
+    if let Err(err) = add_planes(&params, buffer) {
+        params.destroy();
+        return Err(err);
+    }
+
+    params.create(width, height, buffer.format(), Flags::empty());
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_size, plane_args, PlaneArgs};
+    use {DmabufDescriptor, Format, Modifier, Plane, RENDERING};
+
+    #[test]
+    fn plane_mapping() {
+        let descriptor = DmabufDescriptor {
+            width: 64,
+            height: 32,
+            format: Format::NV12,
+            modifier: Modifier(0x0100_0000_0000_0002),
+            planes: vec![Plane { offset: 0, stride: 64 }, Plane { offset: 2048, stride: 64 }],
+            usage: RENDERING
+        };
+        assert_eq!(plane_args(&descriptor), vec![
+            PlaneArgs { index: 0, offset: 0, stride: 64, modifier_hi: 0x0100_0000, modifier_lo: 2 },
+            PlaneArgs { index: 1, offset: 2048, stride: 64, modifier_hi: 0x0100_0000, modifier_lo: 2 }
+        ]);
+
+        // An implicit modifier is sent as INVALID, split the same way.
+        let descriptor = DmabufDescriptor { modifier: Modifier::INVALID, ..descriptor };
+        let args = plane_args(&descriptor);
+        assert!(args.iter().all(|args| args.modifier_hi == 0x00ff_ffff && args.modifier_lo == 0xffff_ffff));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(create_size((64, 32)).unwrap(), (64, 32));
+        assert_eq!(create_size((i32::MAX as u32, 1)).unwrap(), (i32::MAX, 1));
+        assert!(create_size((i32::MAX as u32 + 1, 1)).is_err());
+        assert!(create_size((1, u32::MAX)).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+//! Sharing buffers over Wayland with the `zwp_linux_dmabuf_v1` protocol.
+//!
+//! Clients turn buffers into `wl_buffer`s with the `client` module, and
+//! compositors import the buffers clients send with the `server` module.
+
+#[cfg(feature = "wayland-client")]
+pub mod client;
+#[cfg(feature = "wayland-server")]
+pub mod server;
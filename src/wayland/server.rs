@@ -0,0 +1,316 @@
+//! Importing the buffers clients send.
+//!
+//! Params objects carry a `Params` as their user data, given when the
+//! client sends `create_params`. The compositor's `Dispatch` implementation
+//! for `ZwpLinuxBufferParamsV1` passes every request to
+//! `Importer::handle_request`, which collects the planes, checks them
+//! against the device, imports them as a `Buffer` and answers the client.
+
+use libc;
+
+use wayland_server::{DataInit, Dispatch, DisplayHandle, Resource, WEnum};
+use wayland_server::protocol::wl_buffer::WlBuffer;
+use wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_buffer_params_v1::{Error as ParamsError, Flags, Request, ZwpLinuxBufferParamsV1};
+
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::sync::Mutex;
+
+use super::super::{Buffer, BufferFlags, Device, DmabufDescriptor, Format, Modifier, Plane};
+use error::Result;
+
+/// The number of planes a params object can hold.
+pub const MAX_PLANES: usize = 4;
+
+/// The planes added to a params object, kept as its user data.
+#[derive(Debug, Default)]
+pub struct Params {
+    state: Mutex<State>
+}
+
+#[derive(Debug, Default)]
+struct State {
+    planes: [Option<Added>; MAX_PLANES],
+    used: bool
+}
+
+#[derive(Debug)]
+struct Added {
+    fd: OwnedFd,
+    plane: Plane,
+    modifier: Modifier
+}
+
+impl Params {
+    /// Creates the user data for a new params object.
+    pub fn new() -> Params {
+        Params::default()
+    }
+}
+
+/// What a request to a params object led to.
+pub enum Outcome<'a, F> where F: 'a + AsRef<File> {
+    /// The request was handled, and no buffer was created.
+    Handled,
+    /// The planes were imported, and the client was given a `wl_buffer`.
+    Imported {
+        /// The imported buffer.
+        buffer: Buffer<'a, F>,
+        /// The `wl_buffer` the client attaches to its surfaces.
+        wl_buffer: WlBuffer,
+        /// How the client wants the contents interpreted.
+        flags: Flags
+    },
+    /// The planes could not be imported. The client was sent a `failed`
+    /// event, or a protocol error for invalid requests.
+    Failed
+}
+
+/// Imports client buffers on a device.
+pub struct Importer<'a, F> where F: 'a + AsRef<File> {
+    device: &'a Device<F>,
+    usage: BufferFlags
+}
+
+impl<'a, F> Importer<'a, F> where F: AsRef<File> {
+    /// Creates an importer for buffers used as given on a device.
+    pub fn new(device: &'a Device<F>, usage: BufferFlags) -> Importer<'a, F> {
+        Importer { device: device, usage: usage }
+    }
+
+    /// Handles a request to a params object.
+    ///
+    /// Requests breaking the protocol, such as planes with gaps or a format
+    /// the device does not support, get the matching protocol error. The
+    /// `wl_buffer` of an imported buffer gets the user data `udata` returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the params object does not carry a `Params`.
+    pub fn handle_request<D, U, G>(&self, params: &ZwpLinuxBufferParamsV1, request: Request, dh: &DisplayHandle, data_init: &mut DataInit<D>, udata: G) -> Outcome<'a, F>
+        where D: Dispatch<WlBuffer, U> + 'static,
+              U: Send + Sync + 'static,
+              G: FnOnce() -> U
+    {
+        let data = params.data::<Params>().expect("params object without Params user data");
+        match request {
+            Request::Add { fd, plane_idx, offset, stride, modifier_hi, modifier_lo } => {
+                let mut state = data.state.lock().unwrap();
+                if state.used {
+                    params.post_error(ParamsError::AlreadyUsed, "the params were already used");
+                } else if plane_idx as usize >= MAX_PLANES {
+                    params.post_error(ParamsError::PlaneIdx, format!("plane index {} is out of range", plane_idx));
+                } else if state.planes[plane_idx as usize].is_some() {
+                    params.post_error(ParamsError::PlaneSet, format!("plane {} was already added", plane_idx));
+                } else {
+                    state.planes[plane_idx as usize] = Some(Added {
+                        fd: fd,
+                        plane: Plane { offset: offset, stride: stride },
+                        modifier: Modifier(((modifier_hi as u64) << 32) | modifier_lo as u64)
+                    });
+                }
+                Outcome::Handled
+            },
+            Request::Create { width, height, format, flags } => {
+                let buffer = match self.take(params, data, width, height, format) {
+                    Some(Ok(buffer)) => buffer,
+                    Some(Err(_)) => {
+                        params.failed();
+                        return Outcome::Failed;
+                    },
+                    None => return Outcome::Failed
+                };
+                let wl_buffer = match params.client().map(|client| client.create_resource::<WlBuffer, U, D>(dh, 1, udata())) {
+                    Some(Ok(wl_buffer)) => wl_buffer,
+                    _ => {
+                        params.failed();
+                        return Outcome::Failed;
+                    }
+                };
+                params.created(&wl_buffer);
+                Outcome::Imported { buffer: buffer, wl_buffer: wl_buffer, flags: flags_of(flags) }
+            },
+            Request::CreateImmed { buffer_id, width, height, format, flags } => {
+                // The new `wl_buffer` is left uninitialized on failure, which
+                // is allowed once the client has been sent a protocol error.
+                let buffer = match self.take(params, data, width, height, format) {
+                    Some(Ok(buffer)) => buffer,
+                    Some(Err(err)) => {
+                        params.post_error(ParamsError::InvalidWlBuffer, format!("import failed: {}", err));
+                        return Outcome::Failed;
+                    },
+                    None => return Outcome::Failed
+                };
+                let wl_buffer = data_init.init(buffer_id, udata());
+                Outcome::Imported { buffer: buffer, wl_buffer: wl_buffer, flags: flags_of(flags) }
+            },
+            _ => Outcome::Handled
+        }
+    }
+
+    /// Checks the planes of a create request and imports them. Returns
+    /// `None` if the client was sent a protocol error.
+    fn take(&self, params: &ZwpLinuxBufferParamsV1, data: &Params, width: i32, height: i32, code: u32) -> Option<Result<Buffer<'a, F>>> {
+        let mut state = data.state.lock().unwrap();
+        if state.used {
+            params.post_error(ParamsError::AlreadyUsed, "the params were already used");
+            return None;
+        }
+        state.used = true;
+
+        let slots: Vec<Option<(Plane, Modifier)>> = state.planes.iter()
+            .map(|added| added.as_ref().map(|added| (added.plane, added.modifier)))
+            .collect();
+        let (planes, modifier) = match check_planes(&slots) {
+            Ok(checked) => checked,
+            Err((error, message)) => {
+                params.post_error(error, message);
+                return None;
+            }
+        };
+        let format = match Format::from_fourcc(code) {
+            Some(format) if self.device.is_format_supported(format, self.usage) => format,
+            _ => {
+                params.post_error(ParamsError::InvalidFormat, format!("format {:#x} is not supported", code));
+                return None;
+            }
+        };
+        if modifier != Modifier::INVALID && matches!(self.device.is_modifier_supported(format, modifier), Ok(false)) {
+            params.post_error(ParamsError::InvalidFormat, format!("modifier {:#x} is not supported", modifier.0));
+            return None;
+        }
+
+        let added: Vec<&Added> = state.planes[..planes.len()].iter().filter_map(|added| added.as_ref()).collect();
+        let sizes: Vec<Option<u64>> = added.iter().map(|added| dmabuf_size(added.fd.as_fd())).collect();
+        if let Err((error, message)) = check_bounds(format, width, height, &planes, &sizes) {
+            params.post_error(error, message);
+            return None;
+        }
+
+        let descriptor = DmabufDescriptor {
+            width: width as u32,
+            height: height as u32,
+            format: format,
+            modifier: modifier,
+            planes: planes,
+            usage: self.usage
+        };
+        let fds: Vec<BorrowedFd> = added.iter().map(|added| added.fd.as_fd()).collect();
+        Some(self.device.import_dmabuf(&descriptor, &fds))
+    }
+}
+
+/// A request breaking the protocol, with the error to send.
+type Invalid = (ParamsError, String);
+
+/// Checks that the planes were added contiguously from plane 0 with a
+/// single modifier, and returns them with that modifier.
+fn check_planes(slots: &[Option<(Plane, Modifier)>]) -> ::std::result::Result<(Vec<Plane>, Modifier), Invalid> {
+    let count = slots.iter().take_while(|slot| slot.is_some()).count();
+    if count == 0 || slots[count..].iter().any(|slot| slot.is_some()) {
+        return Err((ParamsError::Incomplete, "the planes are not contiguous from plane 0".to_owned()));
+    }
+
+    let added: Vec<(Plane, Modifier)> = slots[..count].iter().filter_map(|&slot| slot).collect();
+    let modifier = added[0].1;
+    if added.iter().any(|&(_, other)| other != modifier) {
+        return Err((ParamsError::InvalidFormat, "the planes have different modifiers".to_owned()));
+    }
+    Ok((added.iter().map(|&(plane, _)| plane).collect(), modifier))
+}
+
+/// Checks the size of a buffer, and that every plane fits in its dma-buf,
+/// whose size is `None` when the kernel does not report it.
+fn check_bounds(format: Format, width: i32, height: i32, planes: &[Plane], sizes: &[Option<u64>]) -> ::std::result::Result<(), Invalid> {
+    if width <= 0 || height <= 0 {
+        return Err((ParamsError::InvalidDimensions, format!("invalid size {}x{}", width, height)));
+    }
+    for (index, (plane, &size)) in planes.iter().zip(sizes).enumerate() {
+        let size = match size {
+            Some(size) => size,
+            None => continue
+        };
+        let (offset, stride) = (plane.offset as u64, plane.stride as u64);
+        let rows = plane_rows(format, index, height as u64);
+        if offset >= size || offset + stride * rows > size {
+            return Err((ParamsError::OutOfBounds, format!("plane {} does not fit in its dma-buf", index)));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the number of rows in a plane of a buffer `height` pixels high.
+/// The chroma planes of 4:2:0 formats have half as many rows.
+fn plane_rows(format: Format, index: usize, height: u64) -> u64 {
+    match format {
+        Format::NV12 | Format::NV21 | Format::P010 | Format::YUV420 | Format::YVU420 if index > 0 => height.div_ceil(2),
+        _ => height
+    }
+}
+
+/// Returns the size of a dma-buf, or `None` if the kernel does not report
+/// it. `fstat` is used rather than seeking, as the file offset is shared
+/// with the client.
+fn dmabuf_size(fd: BorrowedFd) -> Option<u64> {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 || stat.st_size <= 0 {
+        return None;
+    }
+    Some(stat.st_size as u64)
+}
+
+fn flags_of(flags: WEnum<Flags>) -> Flags {
+    match flags {
+        WEnum::Value(flags) => flags,
+        WEnum::Unknown(bits) => Flags::from_bits_truncate(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_bounds, check_planes, plane_rows};
+    use super::super::super::{Format, Modifier, Plane};
+    use wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_buffer_params_v1::Error as ParamsError;
+
+    const TILED: Modifier = Modifier(0x0100_0000_0000_0001);
+
+    fn plane(offset: u32, stride: u32) -> Plane {
+        Plane { offset: offset, stride: stride }
+    }
+
+    #[test]
+    fn contiguous_planes() {
+        let (y, uv) = (plane(0, 64), plane(1024, 64));
+        assert_eq!(check_planes(&[Some((y, TILED)), Some((uv, TILED)), None, None]).unwrap(), (vec![y, uv], TILED));
+
+        let gap = check_planes(&[Some((y, TILED)), None, Some((uv, TILED)), None]);
+        assert_eq!(gap.unwrap_err().0, ParamsError::Incomplete);
+        assert_eq!(check_planes(&[None, Some((uv, TILED)), None, None]).unwrap_err().0, ParamsError::Incomplete);
+        assert_eq!(check_planes(&[None, None, None, None]).unwrap_err().0, ParamsError::Incomplete);
+
+        let mixed = check_planes(&[Some((y, TILED)), Some((uv, Modifier::LINEAR)), None, None]);
+        assert_eq!(mixed.unwrap_err().0, ParamsError::InvalidFormat);
+    }
+
+    #[test]
+    fn plane_bounds() {
+        // A 64x16 NV12 buffer: 16 rows of luma and 8 of chroma.
+        let planes = [plane(0, 64), plane(1024, 64)];
+        assert!(check_bounds(Format::NV12, 64, 16, &planes, &[Some(1536), Some(1536)]).is_ok());
+        assert_eq!(check_bounds(Format::NV12, 64, 16, &planes, &[Some(1535), Some(1535)]).unwrap_err().0, ParamsError::OutOfBounds);
+
+        // Odd heights round the chroma rows up.
+        assert_eq!(plane_rows(Format::YUV420, 2, 15), 8);
+        assert_eq!(plane_rows(Format::YUV420, 0, 15), 15);
+        assert_eq!(plane_rows(Format::XRGB8888, 1, 15), 15);
+
+        // Offsets past the end, and sizes the kernel does not report.
+        let single = [plane(4096, 256)];
+        assert_eq!(check_bounds(Format::XRGB8888, 64, 16, &single, &[Some(4096)]).unwrap_err().0, ParamsError::OutOfBounds);
+        assert!(check_bounds(Format::XRGB8888, 64, 16, &single, &[None]).is_ok());
+
+        assert_eq!(check_bounds(Format::XRGB8888, 0, 16, &single, &[None]).unwrap_err().0, ParamsError::InvalidDimensions);
+        assert_eq!(check_bounds(Format::XRGB8888, 64, -1, &single, &[None]).unwrap_err().0, ParamsError::InvalidDimensions);
+    }
+}
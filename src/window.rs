@@ -0,0 +1,110 @@
+//! Implementations of the raw-window-handle traits, so renderers can
+//! create surfaces on the GBM platform without `Device::raw` and
+//! `Surface::raw`.
+//!
+//! Surface-target APIs such as wgpu's and glutin's take one value with both
+//! a display and a window handle, which is what a `Window` pairs up.
+
+use raw_window_handle::{
+    DisplayHandle, GbmDisplayHandle, GbmWindowHandle, HandleError, HasDisplayHandle,
+    HasWindowHandle, RawDisplayHandle, RawWindowHandle, WindowHandle
+};
+
+use std::fs::File;
+use std::os::raw::c_void;
+use std::ptr::NonNull;
+
+use super::{Device, Surface};
+
+/// A `Surface` borrowed together with the `Device` it was created from,
+/// implementing both `HasDisplayHandle` and `HasWindowHandle`.
+pub struct Window<'a, F> where F: 'a + AsRef<File> {
+    device: &'a Device<F>,
+    surface: &'a Surface<F>
+}
+
+impl<'a, F> Window<'a, F> where F: AsRef<File> {
+    /// Pairs a surface with its device. libgbm cannot tell which device a
+    /// surface belongs to, so it must be the one that created it.
+    pub fn new(device: &'a Device<F>, surface: &'a Surface<F>) -> Window<'a, F> {
+        Window { device: device, surface: surface }
+    }
+
+    /// Returns the device.
+    pub fn device(&self) -> &'a Device<F> {
+        self.device
+    }
+
+    /// Returns the surface.
+    pub fn surface(&self) -> &'a Surface<F> {
+        self.surface
+    }
+}
+
+/// Wraps a `gbm_device` pointer, which libgbm never makes null.
+fn raw_display(device: *mut c_void) -> Result<RawDisplayHandle, HandleError> {
+    let raw = try!(NonNull::new(device).ok_or(HandleError::Unavailable));
+    Ok(RawDisplayHandle::Gbm(GbmDisplayHandle::new(raw)))
+}
+
+/// Wraps a `gbm_surface` pointer, which libgbm never makes null.
+fn raw_window(surface: *mut c_void) -> Result<RawWindowHandle, HandleError> {
+    let raw = try!(NonNull::new(surface).ok_or(HandleError::Unavailable));
+    Ok(RawWindowHandle::Gbm(GbmWindowHandle::new(raw)))
+}
+
+impl<F> HasDisplayHandle for Device<F> where F: AsRef<File> {
+    fn display_handle(&self) -> Result<DisplayHandle<'_>, HandleError> {
+        let handle = try!(raw_display(unsafe { self.raw() }));
+        // The gbm_device lives as long as the `Device` borrowed here.
+        Ok(unsafe { DisplayHandle::borrow_raw(handle) })
+    }
+}
+
+impl<F> HasWindowHandle for Surface<F> where F: AsRef<File> {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        let handle = try!(raw_window(unsafe { self.raw() }));
+        // The gbm_surface lives as long as the `Surface` borrowed here.
+        Ok(unsafe { WindowHandle::borrow_raw(handle) })
+    }
+}
+
+impl<'a, F> HasDisplayHandle for Window<'a, F> where F: AsRef<File> {
+    fn display_handle(&self) -> Result<DisplayHandle<'_>, HandleError> {
+        self.device.display_handle()
+    }
+}
+
+impl<'a, F> HasWindowHandle for Window<'a, F> where F: AsRef<File> {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        self.surface.window_handle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+
+    use std::os::raw::c_void;
+    use std::ptr::null_mut;
+
+    use super::{raw_display, raw_window};
+
+    #[test]
+    fn gbm_handles() {
+        let mut object = 0u8;
+        let pointer = &mut object as *mut u8 as *mut c_void;
+
+        match raw_display(pointer).unwrap() {
+            RawDisplayHandle::Gbm(handle) => assert_eq!(handle.gbm_device.as_ptr(), pointer),
+            other => panic!("expected a GBM display handle, got {:?}", other)
+        }
+        match raw_window(pointer).unwrap() {
+            RawWindowHandle::Gbm(handle) => assert_eq!(handle.gbm_surface.as_ptr(), pointer),
+            other => panic!("expected a GBM window handle, got {:?}", other)
+        }
+
+        assert!(raw_display(null_mut()).is_err());
+        assert!(raw_window(null_mut()).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+//! Sharing buffers with X servers through DRI3.
+//!
+//! `pixmap_from_buffer` sends a `Buffer` to the server as a pixmap with
+//! `PixmapFromBuffers`, and `buffer_from_pixmap` imports the buffers of a
+//! pixmap with `BuffersFromPixmap`. Both need DRI3 1.2, which
+//! `supports_buffers` checks for.
+//!
+//! X has no formats, only depths and bits per pixel, so only the RGB
+//! formats X servers map them to can be shared. `pixmap_request` and
+//! `reply_descriptor` do the mapping without a connection.
+
+use x11rb::connection::RequestConnection;
+use x11rb::protocol::dri3::{self, BuffersFromPixmapReply, BuffersFromPixmapRequest, ConnectionExt, PixmapFromBuffersRequest};
+use x11rb::protocol::xproto::{Pixmap, Window};
+
+use std::fs::File;
+use std::os::unix::io::{AsFd, BorrowedFd, OwnedFd};
+
+use super::{Buffer, BufferFlags, Device, DmabufDescriptor, Format, Modifier, Plane};
+use error::{Result, Error};
+
+/// The number of planes a DRI3 pixmap can have.
+pub const MAX_PLANES: usize = 4;
+
+/// Returns the depth and bits per pixel of pixmaps in a format.
+pub fn depth_and_bpp(format: Format) -> Option<(u8, u8)> {
+    let depth_and_bpp = match format {
+        Format::R8 => (8, 8),
+        Format::RGB565 => (16, 16),
+        Format::XRGB8888 => (24, 32),
+        Format::XRGB2101010 => (30, 32),
+        Format::ARGB8888 => (32, 32),
+        _ => return None
+    };
+    Some(depth_and_bpp)
+}
+
+/// Returns the format X servers use for pixmaps of a depth and bits per
+/// pixel.
+pub fn format_for_depth(depth: u8, bpp: u8) -> Option<Format> {
+    Format::all().iter().cloned().find(|&format| depth_and_bpp(format) == Some((depth, bpp)))
+}
+
+/// Builds the `PixmapFromBuffers` request for a buffer, with one dma-buf
+/// per plane.
+pub fn pixmap_request(pixmap: Pixmap, window: Window, descriptor: &DmabufDescriptor, fds: Vec<OwnedFd>) -> Result<PixmapFromBuffersRequest> {
+    let (format, planes) = (descriptor.format, &descriptor.planes);
+    let (depth, bpp) = try!(depth_and_bpp(format).ok_or(Error::UnsupportedFormat(format)));
+    if descriptor.width > u16::MAX as u32 || descriptor.height > u16::MAX as u32 {
+        return Err(Error::TooLarge { max: (u16::MAX as u32, u16::MAX as u32), actual: descriptor.size() });
+    }
+    if planes.is_empty() || planes.len() > MAX_PLANES {
+        return Err(Error::InvalidPlaneCount { expected: MAX_PLANES, actual: planes.len() });
+    }
+    if fds.len() != planes.len() {
+        return Err(Error::InvalidPlaneCount { expected: planes.len(), actual: fds.len() });
+    }
+
+    let mut layout = [Plane { offset: 0, stride: 0 }; MAX_PLANES];
+    layout[..planes.len()].copy_from_slice(planes);
+    let request = PixmapFromBuffersRequest {
+        pixmap: pixmap,
+        window: window,
+        width: descriptor.width as u16,
+        height: descriptor.height as u16,
+        stride0: layout[0].stride,
+        offset0: layout[0].offset,
+        stride1: layout[1].stride,
+        offset1: layout[1].offset,
+        stride2: layout[2].stride,
+        offset2: layout[2].offset,
+        stride3: layout[3].stride,
+        offset3: layout[3].offset,
+        depth: depth,
+        bpp: bpp,
+        modifier: descriptor.modifier.0,
+        buffers: fds
+    };
+    Ok(request)
+}
+
+/// Describes the buffers of a pixmap for importing them with the given
+/// usage. The dma-buf of each plane is at the same index in
+/// `reply.buffers`.
+pub fn reply_descriptor(reply: &BuffersFromPixmapReply, usage: BufferFlags) -> Result<DmabufDescriptor> {
+    let format = try!(format_for_depth(reply.depth, reply.bpp)
+        .ok_or(Error::Unsupported("pixmaps of this depth")));
+    if reply.strides.len() != reply.offsets.len() {
+        return Err(Error::InvalidPlaneCount { expected: reply.strides.len(), actual: reply.offsets.len() });
+    }
+    if reply.buffers.len() != reply.strides.len() {
+        return Err(Error::InvalidPlaneCount { expected: reply.strides.len(), actual: reply.buffers.len() });
+    }
+
+    let descriptor = DmabufDescriptor {
+        width: reply.width as u32,
+        height: reply.height as u32,
+        format: format,
+        modifier: Modifier(reply.modifier),
+        planes: reply.offsets.iter().zip(&reply.strides)
+            .map(|(&offset, &stride)| Plane { offset: offset, stride: stride })
+            .collect(),
+        usage: usage
+    };
+    Ok(descriptor)
+}
+
+/// Returns true if the server supports DRI3 1.2, which added the requests
+/// for buffers with modifiers and multiple planes.
+pub fn supports_buffers<C>(conn: &C) -> Result<bool> where C: RequestConnection {
+    if try!(conn.extension_information(dri3::X11_EXTENSION_NAME)).is_none() {
+        return Ok(false);
+    }
+    let version = try!(try!(conn.dri3_query_version(1, 2)).reply());
+    Ok((version.major_version, version.minor_version) >= (1, 2))
+}
+
+/// Creates a pixmap on the screen of `window` that shares the memory of a
+/// buffer.
+///
+/// `pixmap` is a fresh id from `generate_id`. The request is only sent, so
+/// errors are reported when the connection is next flushed and checked.
+pub fn pixmap_from_buffer<C, F>(conn: &C, buffer: &Buffer<F>, pixmap: Pixmap, window: Window) -> Result<()>
+    where C: RequestConnection,
+          F: AsRef<File>
+{
+    let descriptor = try!(buffer.descriptor());
+    let fds = try!(buffer.plane_fds());
+    let request = try!(pixmap_request(pixmap, window, &descriptor, fds));
+    try!(conn.send_trait_request_without_reply(request));
+    Ok(())
+}
+
+/// Imports the buffers of a pixmap into a device.
+pub fn buffer_from_pixmap<'a, C, F>(conn: &C, device: &'a Device<F>, pixmap: Pixmap, flags: BufferFlags) -> Result<Buffer<'a, F>>
+    where C: RequestConnection,
+          F: AsRef<File>
+{
+    let request = BuffersFromPixmapRequest { pixmap: pixmap };
+    let reply = try!(try!(conn.send_trait_request_with_reply_with_fds(request)).reply());
+    let descriptor = try!(reply_descriptor(&reply, flags));
+    let fds: Vec<BorrowedFd> = reply.buffers.iter().map(|fd| fd.as_fd()).collect();
+    device.import_dmabuf(&descriptor, &fds)
+}
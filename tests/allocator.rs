@@ -0,0 +1,58 @@
+extern crate gbm;
+
+use gbm::{Allocator, BufferObject, Format};
+use gbm::software::Device;
+use std::os::unix::io::{AsFd, BorrowedFd};
+
+/// Exports a buffer, imports it again and checks that both share memory.
+fn round_trip<A: Allocator>(allocator: &A) {
+    let mut buffer = allocator.create_buffer((32, 8), Format::ARGB8888, gbm::RENDERING).unwrap();
+    allocator.map_buffer(&mut buffer, |data, _| data[0] = 0x5a).unwrap();
+
+    let descriptor = BufferObject::descriptor(&buffer).unwrap();
+    let fds = allocator.export_buffer(&buffer).unwrap();
+    let borrowed: Vec<BorrowedFd> = fds.iter().map(|fd| fd.as_fd()).collect();
+    let mut imported = allocator.import_buffer(&descriptor, &borrowed).unwrap();
+    assert_eq!(imported.size(), (32, 8));
+    assert_eq!(imported.stride(), buffer.stride());
+    assert_eq!(imported.format(), Format::ARGB8888 as u32);
+    assert_eq!(imported.descriptor().unwrap(), descriptor);
+    assert_eq!(allocator.map_buffer(&mut imported, |data, _| data[0]).unwrap(), 0x5a);
+
+    // The exports are new file descriptors, so closing them keeps the buffer.
+    drop(borrowed);
+    drop(fds);
+    allocator.map_buffer(&mut buffer, |data, _| data[0] = 0xa5).unwrap();
+    assert_eq!(allocator.map_buffer(&mut imported, |data, _| data[0]).unwrap(), 0xa5);
+}
+
+#[test]
+fn export_import() {
+    round_trip(&Device::new());
+}
+
+#[test]
+fn import_errors() {
+    let dev = Device::new();
+    let buffer = dev.create_buffer((16, 16), Format::XRGB8888, gbm::RENDERING).unwrap();
+    let fds = dev.export_buffer(&buffer).unwrap();
+    let fd = fds[0].as_fd();
+
+    // The dma-buf must hold every row at the given stride.
+    let mut descriptor = BufferObject::descriptor(&buffer).unwrap();
+    descriptor.planes[0].stride *= 2;
+    assert!(dev.import_buffer(&descriptor, &[fd]).is_err());
+
+    // Software buffers have a single plane at the start of the dma-buf.
+    let descriptor = BufferObject::descriptor(&buffer).unwrap();
+    assert!(dev.import_buffer(&descriptor, &[fd, fd]).is_err());
+    assert!(dev.import_buffer(&descriptor, &[]).is_err());
+    let mut offset = descriptor.clone();
+    offset.planes[0].offset = 64;
+    assert!(dev.import_buffer(&offset, &[fd]).is_err());
+    let mut tiled = descriptor.clone();
+    tiled.modifier = gbm::Modifier(0x0100_0000_0000_0001);
+    assert!(dev.import_buffer(&tiled, &[fd]).is_err());
+
+    assert!(dev.create_buffer((16, 16), Format::NV12, gbm::RENDERING).is_err());
+}
@@ -0,0 +1,45 @@
+extern crate gbm;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+
+use gbm::{DmabufDescriptor, Format, Modifier, Plane};
+use gbm::software::Device;
+
+#[test]
+fn linear() {
+    let descriptor = DmabufDescriptor::linear((32, 16), Format::XRGB8888, 128, gbm::RENDERING);
+    assert_eq!(descriptor.size(), (32, 16));
+    assert_eq!(descriptor.modifier, Modifier::LINEAR);
+    assert_eq!(descriptor.planes, vec![Plane { offset: 0, stride: 128 }]);
+
+    let dev = Device::new();
+    let buffer = dev.buffer((32, 16), Format::XRGB8888, gbm::RENDERING | gbm::WRITE).unwrap();
+    let descriptor = buffer.descriptor();
+    assert_eq!(descriptor.size(), buffer.size());
+    assert_eq!(descriptor.format, Format::XRGB8888);
+    assert_eq!(descriptor.planes[0].stride, buffer.stride());
+    assert_eq!(descriptor.usage, gbm::RENDERING | gbm::WRITE);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let descriptor = DmabufDescriptor {
+        width: 1920,
+        height: 1080,
+        format: Format::NV12,
+        modifier: Modifier(0x0100_0000_0000_0002),
+        planes: vec![Plane { offset: 0, stride: 2048 }, Plane { offset: 2048 * 1088, stride: 2048 }],
+        usage: gbm::SCANOUT | gbm::RENDERING
+    };
+    let json = serde_json::to_string(&descriptor).unwrap();
+    assert_eq!(serde_json::from_str::<DmabufDescriptor>(&json).unwrap(), descriptor);
+
+    // Usage is sent as its bits, so unknown flags from a newer sender are
+    // dropped rather than failing.
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["usage"], (gbm::SCANOUT | gbm::RENDERING).bits());
+    let json = json.replace(&format!("\"usage\":{}", value["usage"]), "\"usage\":4294967295");
+    let parsed: DmabufDescriptor = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.usage, gbm::BufferFlags::all());
+}
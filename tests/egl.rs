@@ -0,0 +1,47 @@
+#![cfg(feature = "egl")]
+
+extern crate gbm;
+extern crate khronos_egl;
+
+use gbm::{DmabufDescriptor, Format, Modifier, Plane, RENDERING};
+use gbm::egl::image_attributes;
+use khronos_egl::{ATTRIB_NONE, HEIGHT, WIDTH};
+
+fn descriptor(modifier: Modifier) -> DmabufDescriptor {
+    DmabufDescriptor {
+        width: 64,
+        height: 16,
+        format: Format::NV12,
+        modifier: modifier,
+        planes: vec![Plane { offset: 0, stride: 64 }, Plane { offset: 1024, stride: 64 }],
+        usage: RENDERING
+    }
+}
+
+#[test]
+fn dmabuf_attributes() {
+    let modifier = Modifier(0x0100_0000_0000_0002);
+    let attributes = image_attributes(&descriptor(modifier), &[7, 8], true).unwrap();
+    assert_eq!(attributes, vec![
+        WIDTH as usize, 64,
+        HEIGHT as usize, 16,
+        0x3271, Format::NV12 as usize,
+        0x3272, 7, 0x3273, 0, 0x3274, 64,
+        0x3443, 2, 0x3444, 0x0100_0000,
+        0x3275, 8, 0x3276, 1024, 0x3277, 64,
+        0x3445, 2, 0x3446, 0x0100_0000,
+        ATTRIB_NONE
+    ]);
+
+    // The modifier is left out without the extension, or when implicit.
+    let without = image_attributes(&descriptor(modifier), &[7, 8], false).unwrap();
+    assert_eq!(without.len(), 6 + 2 * 6 + 1);
+    assert!(!without.contains(&0x3443));
+    let implicit = image_attributes(&descriptor(Modifier::INVALID), &[7, 8], true).unwrap();
+    assert_eq!(implicit, without);
+
+    assert!(image_attributes(&descriptor(modifier), &[7], true).is_err());
+    let mut five = descriptor(modifier);
+    five.planes = vec![Plane { offset: 0, stride: 64 }; 5];
+    assert!(image_attributes(&five, &[7; 5], true).is_err());
+}
@@ -0,0 +1,74 @@
+extern crate gbm;
+
+use gbm::{Format, Modifier};
+use gbm::feedback::{self, Feedback, FormatTable, Tranche};
+use std::os::unix::io::AsFd;
+
+const X_TILED: Modifier = Modifier(0x0100_0000_0000_0001);
+
+#[test]
+fn table_bytes() {
+    let mut table = FormatTable::new();
+    assert_eq!(table.insert(Format::XRGB8888, X_TILED).unwrap(), 0);
+    assert_eq!(table.insert(Format::ARGB8888, Modifier::LINEAR).unwrap(), 1);
+    assert_eq!(table.insert(Format::XRGB8888, X_TILED).unwrap(), 0);
+
+    let bytes = table.to_bytes();
+    assert_eq!(bytes.len(), 2 * feedback::ENTRY_SIZE);
+    assert_eq!(&bytes[0..4], &(Format::XRGB8888 as u32).to_ne_bytes());
+    assert_eq!(&bytes[4..8], &[0; 4]);
+    assert_eq!(&bytes[8..16], &X_TILED.0.to_ne_bytes());
+    assert_eq!(FormatTable::from_bytes(&bytes).unwrap(), table);
+
+    // Unknown formats keep their index but cannot be resolved.
+    let mut unknown = bytes.clone();
+    unknown[16..20].copy_from_slice(&0x2020_2020u32.to_ne_bytes());
+    let unknown = FormatTable::from_bytes(&unknown).unwrap();
+    assert_eq!(unknown.len(), 2);
+    assert_eq!(unknown.get(0), Some((Format::XRGB8888, X_TILED)));
+    assert_eq!(unknown.get(1), None);
+    assert_eq!(unknown.get(2), None);
+
+    assert!(FormatTable::from_bytes(&bytes[..20]).is_err());
+}
+
+#[test]
+fn memfd() {
+    let mut feedback = Feedback::new(0xe200);
+    feedback.add_tranche(0xe280, feedback::TRANCHE_SCANOUT, vec![
+        (Format::XRGB8888, X_TILED),
+        (Format::XRGB8888, Modifier::LINEAR)
+    ]).unwrap();
+    feedback.add_tranche(0xe200, feedback::TrancheFlags::empty(), vec![
+        (Format::XRGB8888, Modifier::LINEAR),
+        (Format::NV12, Modifier::INVALID)
+    ]).unwrap();
+    assert_eq!(feedback.table.len(), 3);
+    assert_eq!(feedback.tranches[1].formats, vec![1, 2]);
+
+    let (fd, size) = feedback.table.to_memfd().unwrap();
+    assert_eq!(size as usize, 3 * feedback::ENTRY_SIZE);
+    let table = FormatTable::from_fd(fd.as_fd(), size).unwrap();
+    assert_eq!(table, feedback.table);
+    assert_eq!(table.resolve(&feedback.tranches[1]), vec![
+        (Format::XRGB8888, Modifier::LINEAR),
+        (Format::NV12, Modifier::INVALID)
+    ]);
+
+    // The table is sealed against writes.
+    let mut file = std::fs::File::from(fd);
+    assert!(std::io::Write::write_all(&mut file, &[0]).is_err());
+}
+
+#[test]
+fn tranche_bytes() {
+    let tranche = Tranche { device: 0xe280, flags: feedback::TRANCHE_SCANOUT, formats: vec![0, 2, 0x1234] };
+    let bytes = tranche.formats_to_bytes();
+    assert_eq!(bytes.len(), 6);
+    assert_eq!(Tranche::formats_from_bytes(&bytes).unwrap(), tranche.formats);
+    assert!(Tranche::formats_from_bytes(&bytes[..5]).is_err());
+
+    let device = feedback::device_to_bytes(0xe280);
+    assert_eq!(feedback::device_from_bytes(&device).unwrap(), 0xe280);
+    assert!(feedback::device_from_bytes(&device[..4]).is_err());
+}
@@ -0,0 +1,11 @@
+extern crate gbm;
+
+use gbm::{Format, Modifier};
+
+#[test]
+fn fourcc() {
+    assert_eq!(Format::XRGB8888 as u32, 0x34325258);
+    assert_eq!(Format::from_fourcc(0x34325241), Some(Format::ARGB8888));
+    assert_eq!(Format::from_fourcc(0), None);
+    assert_eq!(Modifier::INVALID.vendor(), 0);
+}
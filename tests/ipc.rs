@@ -0,0 +1,63 @@
+extern crate gbm;
+
+use gbm::{DmabufDescriptor, Format, Modifier, Plane, RENDERING, SCANOUT};
+use gbm::ipc;
+
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsFd;
+use std::os::unix::net::UnixStream;
+
+fn descriptor() -> DmabufDescriptor {
+    DmabufDescriptor {
+        width: 64,
+        height: 16,
+        format: Format::NV12,
+        modifier: Modifier::LINEAR,
+        planes: vec![Plane { offset: 0, stride: 64 }, Plane { offset: 1024, stride: 64 }],
+        usage: SCANOUT | RENDERING
+    }
+}
+
+#[test]
+fn message_bytes() {
+    let descriptor = descriptor();
+    let bytes = ipc::to_bytes(&descriptor).unwrap();
+    assert_eq!(&bytes[0..4], &ipc::MESSAGE_VERSION.to_ne_bytes());
+    assert_eq!(&bytes[12..16], &(Format::NV12 as u32).to_ne_bytes());
+    assert_eq!(&bytes[40..44], &1024u32.to_ne_bytes());
+    assert_eq!(&bytes[28..32], &(SCANOUT | RENDERING).bits().to_ne_bytes());
+    assert_eq!(ipc::from_bytes(&bytes).unwrap(), descriptor);
+
+    assert!(ipc::from_bytes(&bytes[..32]).is_err());
+    let mut bad = bytes;
+    bad[0] = 0xff;
+    assert!(ipc::from_bytes(&bad).is_err());
+    let mut bad = bytes;
+    bad[24..28].copy_from_slice(&5u32.to_ne_bytes());
+    assert!(ipc::from_bytes(&bad).is_err());
+
+    let mut empty = descriptor;
+    empty.planes.clear();
+    assert!(ipc::to_bytes(&empty).is_err());
+}
+
+#[test]
+fn send_and_recv() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let file = File::open("/dev/null").unwrap();
+    let other = File::open("/proc/self/exe").unwrap();
+
+    let descriptor = descriptor();
+    ipc::send(&a, &descriptor, &[file.as_fd(), other.as_fd()]).unwrap();
+    let (received, fds) = ipc::recv(&b).unwrap();
+    assert_eq!(received, descriptor);
+    assert_eq!(fds.len(), 2);
+
+    // The received descriptors refer to the files that were sent.
+    let received = File::from(fds.into_iter().nth(1).unwrap());
+    assert_eq!(received.metadata().unwrap().ino(), other.metadata().unwrap().ino());
+
+    // The number of descriptors must match the planes.
+    assert!(ipc::send(&a, &descriptor, &[file.as_fd()]).is_err());
+}
@@ -0,0 +1,73 @@
+extern crate gbm;
+
+use gbm::{Format, Modifier};
+use gbm::kms::parse_in_formats;
+
+const X_TILED: u64 = 0x0100_0000_0000_0001;
+
+/// Builds a `drm_format_modifier_blob` from formats and modifier entries of
+/// (format mask, offset, modifier).
+fn blob(formats: &[u32], modifiers: &[(u64, u32, u64)]) -> Vec<u8> {
+    let formats_offset = 24;
+    let modifiers_offset = formats_offset + formats.len() as u32 * 4;
+    let mut bytes = Vec::new();
+    for &value in &[1, 0, formats.len() as u32, formats_offset, modifiers.len() as u32, modifiers_offset] {
+        bytes.extend_from_slice(&u32::to_ne_bytes(value));
+    }
+    for &format in formats {
+        bytes.extend_from_slice(&format.to_ne_bytes());
+    }
+    for &(mask, offset, modifier) in modifiers {
+        bytes.extend_from_slice(&mask.to_ne_bytes());
+        bytes.extend_from_slice(&offset.to_ne_bytes());
+        bytes.extend_from_slice(&0u32.to_ne_bytes());
+        bytes.extend_from_slice(&modifier.to_ne_bytes());
+    }
+    bytes
+}
+
+#[test]
+fn in_formats() {
+    let formats = [Format::XRGB8888 as u32, Format::ARGB8888 as u32, 0x2020_2020, Format::NV12 as u32];
+    let bytes = blob(&formats, &[(0b1111, 0, 0), (0b0001, 0, X_TILED)]);
+    assert_eq!(bytes.len(), 24 + 4 * 4 + 2 * 24);
+
+    // Unknown formats are skipped, and pairs are grouped by format.
+    assert_eq!(parse_in_formats(&bytes).unwrap(), vec![
+        (Format::XRGB8888, Modifier::LINEAR),
+        (Format::XRGB8888, Modifier(X_TILED)),
+        (Format::ARGB8888, Modifier::LINEAR),
+        (Format::NV12, Modifier::LINEAR)
+    ]);
+}
+
+#[test]
+fn in_formats_offset() {
+    // Entries cover 64 formats starting at their offset.
+    let mut formats = vec![Format::XRGB8888 as u32; 64];
+    formats.push(Format::ABGR8888 as u32);
+    let bytes = blob(&formats, &[(0b11, 63, X_TILED)]);
+    assert_eq!(parse_in_formats(&bytes).unwrap(), vec![
+        (Format::XRGB8888, Modifier(X_TILED)),
+        (Format::ABGR8888, Modifier(X_TILED))
+    ]);
+}
+
+#[test]
+fn in_formats_invalid() {
+    let bytes = blob(&[Format::XRGB8888 as u32], &[(1, 0, 0)]);
+    assert!(parse_in_formats(&bytes[..20]).is_err());
+    assert!(parse_in_formats(&bytes[..bytes.len() - 1]).is_err());
+
+    // Later versions only add to the blob, so they are parsed as well.
+    let mut version = bytes.clone();
+    version[..4].copy_from_slice(&2u32.to_ne_bytes());
+    assert_eq!(parse_in_formats(&version).unwrap(), parse_in_formats(&bytes).unwrap());
+    version[..4].copy_from_slice(&0u32.to_ne_bytes());
+    assert!(parse_in_formats(&version).is_err());
+
+    // A count pointing past the end of the blob.
+    let mut count = bytes;
+    count[8..12].copy_from_slice(&u32::MAX.to_ne_bytes());
+    assert!(parse_in_formats(&count).is_err());
+}
@@ -1,9 +1,31 @@
+//! Tests against libgbm on the first DRM device. They need a GPU, so they
+//! are ignored unless run with `cargo test -- --ignored`.
+
 extern crate gbm;
+#[cfg(feature = "drm")]
+extern crate drm;
+#[cfg(feature = "raw-window-handle")]
+extern crate raw_window_handle;
+
+use std::fs::{File, OpenOptions};
+
+/// The card the tests run on, owned by the `Device`.
+struct Card(File);
+
+impl AsRef<File> for Card {
+    fn as_ref(&self) -> &File {
+        &self.0
+    }
+}
+
+fn card() -> Card {
+    Card(OpenOptions::new().read(true).write(true).open("/dev/dri/card0").unwrap())
+}
 
 #[test]
+#[ignore = "needs /dev/dri/card0"]
 fn user_data() {
-    let file = std::fs::OpenOptions::new().read(true).write(true).open("/dev/dri/card0").unwrap();
-    let dev = gbm::Device::from_file(&file).unwrap();
+    let dev = gbm::Device::from_file(card()).unwrap();
     let format = gbm::Format::XRGB8888;
     let flags = gbm::SCANOUT | gbm::RENDERING;
     let buffer = dev.buffer((16, 16), format, flags).unwrap();
@@ -40,3 +62,150 @@ fn user_data() {
     assert_eq!(get, None);
 }
 
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn write() {
+    let dev = gbm::Device::from_file(card()).unwrap();
+    let format = gbm::Format::ARGB8888;
+
+    // Writing requires the WRITE usage flag.
+    let mut buffer = dev.buffer((64, 64), format, gbm::CURSOR).unwrap();
+    assert!(buffer.write_packed(&[0; 64 * 64 * 4]).is_err());
+
+    let mut buffer = dev.buffer((64, 64), format, gbm::CURSOR | gbm::WRITE).unwrap();
+
+    // The data must cover the whole buffer.
+    assert!(buffer.write_packed(&[0; 16]).is_err());
+    buffer.write_packed(&[0xff; 64 * 64 * 4]).unwrap();
+
+    let stride = buffer.stride() as usize;
+    buffer.write(&vec![0; stride * 64]).unwrap();
+}
+
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn cursor() {
+    let dev = gbm::Device::from_file(card()).unwrap();
+    let mut cursor = dev.cursor().unwrap();
+    assert_eq!(cursor.size(), (64, 64));
+
+    // Each upload should land in a different buffer than the current one.
+    let first = unsafe { cursor.buffer().raw() };
+    let second = unsafe { cursor.set_image(&[0xff; 16 * 16 * 4], (16, 16), (4, 4)).unwrap().raw() };
+    assert!(first != second);
+    assert_eq!(cursor.hotspot(), (4, 4));
+
+    // Images larger than the cursor are rejected.
+    assert!(cursor.set_image(&[0; 128 * 128 * 4], (128, 128), (0, 0)).is_err());
+}
+
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn prime_share() {
+    let src = gbm::Device::from_file(card()).unwrap();
+    let dst = gbm::Device::from_file(card()).unwrap();
+    let format = gbm::Format::XRGB8888;
+    let mut buffer = src.buffer((64, 64), format, gbm::RENDERING).unwrap();
+
+    // Both devices use the same GPU, so the buffer is imported as it is.
+    let mut shared = gbm::prime::share(&src, &mut buffer, &[gbm::Modifier::LINEAR], &dst, gbm::SCANOUT).unwrap();
+    assert_eq!(shared.buffer.size(), (64, 64));
+    assert_eq!(shared.buffer.format(), format as u32);
+    match shared.path {
+        gbm::prime::SharePath::Direct(_) => (),
+        path => panic!("unexpected path {:?}", path)
+    }
+
+    // Directly imported buffers share memory, so there is nothing to copy.
+    shared.update(&mut buffer).unwrap();
+}
+
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn descriptor_import() {
+    use std::os::unix::io::AsFd;
+
+    let dev = gbm::Device::from_file(card()).unwrap();
+    let buffer = dev.buffer((64, 32), gbm::Format::XRGB8888, gbm::RENDERING).unwrap();
+
+    let descriptor = buffer.descriptor().unwrap();
+    assert_eq!(descriptor.size(), (64, 32));
+    assert_eq!(descriptor.usage, gbm::RENDERING);
+
+    let fds = buffer.plane_fds().unwrap();
+    let fds: Vec<_> = fds.iter().map(|fd| fd.as_fd()).collect();
+    let imported = dev.import_dmabuf(&descriptor, &fds).unwrap();
+    assert_eq!(imported.descriptor().unwrap(), descriptor);
+
+    let like = dev.buffer_like(&descriptor).unwrap();
+    assert_eq!(like.size(), (64, 32));
+    assert_eq!(like.format(), gbm::Format::XRGB8888 as u32);
+    assert_eq!(like.usage(), gbm::RENDERING);
+}
+
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn buffer_builder_fallback() {
+    let dev = gbm::Device::from_file(card()).unwrap();
+
+    // No driver knows this modifier, so the driver's own layout is used.
+    let unknown = gbm::Modifier(0x0fff_ffff_ffff_fff0);
+    let built = dev.buffer_builder()
+        .size((64, 64))
+        .format(gbm::Format::XRGB8888)
+        .modifiers(&[unknown, gbm::Modifier::INVALID])
+        .usage(gbm::RENDERING)
+        .name("fallback")
+        .build()
+        .unwrap();
+    assert_eq!(built.value.size(), (64, 64));
+    assert_eq!(built.config.format, gbm::Format::XRGB8888);
+    // The modifier the driver picked is recorded, not the offered ones.
+    assert!(built.config.modifiers.len() <= 1);
+    assert!(!built.config.modifiers.contains(&unknown));
+    assert_eq!(built.config.modifiers, built.value.modifier().into_iter().filter(|&m| m != gbm::Modifier::INVALID).collect::<Vec<_>>());
+    assert_eq!(built.config.name, Some("fallback".to_owned()));
+
+    let built = dev.surface_builder()
+        .size((64, 64))
+        .format(gbm::Format::XRGB8888)
+        .usage(gbm::RENDERING)
+        .build()
+        .unwrap();
+    assert_eq!(built.value.format(), gbm::Format::XRGB8888);
+}
+
+#[cfg(feature = "raw-window-handle")]
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn window_handles() {
+    use raw_window_handle::{HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle};
+
+    let dev = gbm::Device::from_file(card()).unwrap();
+    let surface = dev.surface((64, 64), gbm::Format::XRGB8888, gbm::SCANOUT | gbm::RENDERING).unwrap();
+    let window = gbm::Window::new(&dev, &surface);
+
+    match window.display_handle().unwrap().as_raw() {
+        RawDisplayHandle::Gbm(handle) => assert_eq!(handle.gbm_device.as_ptr(), unsafe { dev.raw() }),
+        other => panic!("expected a GBM display handle, got {:?}", other)
+    }
+    match window.window_handle().unwrap().as_raw() {
+        RawWindowHandle::Gbm(handle) => assert_eq!(handle.gbm_surface.as_ptr(), unsafe { surface.raw() }),
+        other => panic!("expected a GBM window handle, got {:?}", other)
+    }
+}
+
+#[cfg(feature = "drm")]
+#[test]
+#[ignore = "needs /dev/dri/card0"]
+fn drm_buffer_traits() {
+    use drm::buffer::{Buffer, DrmFourcc, PlanarBuffer};
+
+    let dev = gbm::Device::from_file(card()).unwrap();
+    let buffer = dev.buffer((64, 32), gbm::Format::XRGB8888, gbm::SCANOUT | gbm::RENDERING).unwrap();
+    assert_eq!(Buffer::size(&buffer), (64, 32));
+    assert_eq!(Buffer::format(&buffer), DrmFourcc::Xrgb8888);
+    assert_eq!(buffer.pitch(), buffer.stride());
+    assert_eq!(PlanarBuffer::pitches(&buffer)[0], buffer.stride());
+    assert_eq!(PlanarBuffer::handles(&buffer)[0], Some(Buffer::handle(&buffer)));
+}
@@ -0,0 +1,82 @@
+extern crate gbm;
+
+use gbm::{Format, Layout, Modifier};
+use gbm::negotiate::{Choice, Negotiator};
+
+const X_TILED: Modifier = Modifier(0x0100_0000_0000_0001);
+const Y_TILED_CCS: Modifier = Modifier(0x0100_0000_0000_0004);
+const AMD_DCC: Modifier = Modifier(0x0200_0000_0000_2000);
+
+#[test]
+fn layout() {
+    assert_eq!(Modifier::LINEAR.layout(), Layout::Linear);
+    assert_eq!(Modifier::INVALID.layout(), Layout::Implicit);
+    assert_eq!(X_TILED.layout(), Layout::Tiled);
+    assert_eq!(Y_TILED_CCS.layout(), Layout::Compressed);
+    assert_eq!(AMD_DCC.layout(), Layout::Compressed);
+    assert_eq!(Modifier(0x0200_0000_0000_0001).layout(), Layout::Tiled);
+    assert!(Layout::Compressed > Layout::Tiled && Layout::Tiled > Layout::Linear);
+}
+
+#[test]
+fn common_modifiers() {
+    let kms = vec![
+        (Format::XRGB8888, Modifier::LINEAR),
+        (Format::XRGB8888, X_TILED),
+        (Format::XRGB8888, Y_TILED_CCS),
+        (Format::ARGB8888, Modifier::LINEAR)
+    ];
+    let egl = vec![
+        (Format::ARGB8888, Modifier::LINEAR),
+        (Format::XRGB8888, Modifier::LINEAR),
+        (Format::XRGB8888, Y_TILED_CCS),
+        (Format::XRGB8888, X_TILED),
+        (Format::NV12, Modifier::LINEAR)
+    ];
+
+    // Modifiers are ranked compressed, then tiled, then linear.
+    let choices = Negotiator::new().consumer(kms.clone()).consumer(egl.clone()).choices();
+    assert_eq!(choices, vec![
+        Choice { format: Format::XRGB8888, modifiers: vec![Y_TILED_CCS, X_TILED, Modifier::LINEAR] },
+        Choice { format: Format::ARGB8888, modifiers: vec![Modifier::LINEAR] }
+    ]);
+
+    // A consumer only supporting linear buffers restricts the choice.
+    let choice = Negotiator::new()
+        .consumer(kms.clone())
+        .consumer(egl.clone())
+        .consumer(vec![(Format::XRGB8888, Modifier::LINEAR)])
+        .negotiate();
+    assert_eq!(choice, Some(Choice { format: Format::XRGB8888, modifiers: vec![Modifier::LINEAR] }));
+
+    // Nothing in common.
+    let choice = Negotiator::new().consumer(kms).consumer(vec![(Format::NV12, Modifier::LINEAR)]).negotiate();
+    assert_eq!(choice, None);
+    assert_eq!(Negotiator::new().negotiate(), None);
+}
+
+#[test]
+fn format_ranking() {
+    let consumer = vec![
+        (Format::ARGB8888, Y_TILED_CCS),
+        (Format::XRGB8888, Modifier::LINEAR),
+        (Format::XBGR8888, X_TILED)
+    ];
+
+    // The better layout wins by default.
+    let choice = Negotiator::new().consumer(consumer.clone()).negotiate().unwrap();
+    assert_eq!(choice.format, Format::ARGB8888);
+    assert_eq!(choice.layout(), Layout::Compressed);
+
+    // Primary planes prefer formats without alpha.
+    let choice = Negotiator::new().consumer(consumer.clone()).primary_plane().negotiate().unwrap();
+    assert_eq!(choice.format, Format::XBGR8888);
+
+    // Preferred formats come first, and others are ignored.
+    let choices = Negotiator::new()
+        .consumer(consumer)
+        .formats(&[Format::XRGB8888, Format::XBGR8888])
+        .choices();
+    let formats: Vec<Format> = choices.iter().map(|choice| choice.format).collect();
+    assert_eq!(formats, vec![Format::XRGB8888, Format::XBGR8888]);
+}
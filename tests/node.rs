@@ -0,0 +1,151 @@
+extern crate gbm;
+
+use gbm::node::{self, DrmNode, GpuKind, Purpose, Selector};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// Creates an empty fake sysfs root unique to a test.
+fn sysfs(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("gbm-sysfs-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("class/drm")).unwrap();
+    fs::create_dir_all(root.join("bus/pci/drivers")).unwrap();
+    root
+}
+
+/// Adds a PCI device with the given nodes to a fake sysfs root.
+fn add_device(root: &Path, slot: &str, driver: &str, ids: (&str, &str), boot_vga: bool, nodes: &[&str]) {
+    let device = root.join("devices/pci0000:00").join(slot);
+    fs::create_dir_all(&device).unwrap();
+    fs::write(device.join("vendor"), format!("{}\n", ids.0)).unwrap();
+    fs::write(device.join("device"), format!("{}\n", ids.1)).unwrap();
+    fs::write(device.join("boot_vga"), if boot_vga { "1\n" } else { "0\n" }).unwrap();
+
+    let driver_dir = root.join("bus/pci/drivers").join(driver);
+    fs::create_dir_all(&driver_dir).unwrap();
+    symlink(&driver_dir, device.join("driver")).unwrap();
+
+    for name in nodes {
+        let node = device.join("drm").join(name);
+        fs::create_dir_all(&node).unwrap();
+        symlink(&device, node.join("device")).unwrap();
+        symlink(&node, root.join("class/drm").join(name)).unwrap();
+    }
+}
+
+#[test]
+fn enumerate() {
+    let root = sysfs("enumerate");
+    add_device(&root, "0000:00:02.0", "i915", ("0x8086", "0x9a49"), true, &["card0", "renderD128"]);
+    add_device(&root, "0000:01:00.0", "nouveau", ("0x10de", "0x1f95"), false, &["card1", "renderD129"]);
+
+    // Connectors and other entries are ignored.
+    fs::create_dir_all(root.join("class/drm/card0-eDP-1")).unwrap();
+    fs::write(root.join("class/drm/version"), "drm 1.1.0 20060810\n").unwrap();
+
+    let nodes = node::enumerate_in(&root).unwrap();
+    assert_eq!(nodes, vec![
+        DrmNode {
+            primary: Some("/dev/dri/card0".into()),
+            render: Some("/dev/dri/renderD128".into()),
+            driver: Some("i915".to_owned()),
+            vendor_id: Some(0x8086),
+            device_id: Some(0x9a49),
+            boot_vga: true,
+            kind: GpuKind::Integrated
+        },
+        DrmNode {
+            primary: Some("/dev/dri/card1".into()),
+            render: Some("/dev/dri/renderD129".into()),
+            driver: Some("nouveau".to_owned()),
+            vendor_id: Some(0x10de),
+            device_id: Some(0x1f95),
+            boot_vga: false,
+            kind: GpuKind::Discrete
+        }
+    ]);
+    assert_eq!(nodes[1].path(), Some(Path::new("/dev/dri/renderD129")));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn render_node_for() {
+    let root = sysfs("render");
+    add_device(&root, "0000:00:02.0", "i915", ("0x8086", "0x9a49"), true, &["card0", "renderD128"]);
+    add_device(&root, "0000:03:00.0", "simpledrm", ("0x0000", "0x0000"), false, &["card1"]);
+
+    let render = node::render_node_for_in(&root, Path::new("/dev/dri/card0")).unwrap();
+    assert_eq!(render, Some("/dev/dri/renderD128".into()));
+
+    // Devices without a render node, and unknown nodes, have none.
+    assert_eq!(node::render_node_for_in(&root, Path::new("/dev/dri/card1")).unwrap(), None);
+    assert_eq!(node::render_node_for_in(&root, Path::new("/dev/dri/card7")).unwrap(), None);
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// Builds a PCI device node as enumeration would.
+fn device(card: u32, render: bool, driver: &str, boot_vga: bool, kind: GpuKind) -> DrmNode {
+    DrmNode {
+        primary: Some(format!("/dev/dri/card{}", card).into()),
+        render: if render { Some(format!("/dev/dri/renderD{}", 128 + card).into()) } else { None },
+        driver: Some(driver.to_owned()),
+        vendor_id: None,
+        device_id: None,
+        boot_vga: boot_vga,
+        kind: kind
+    }
+}
+
+#[test]
+fn select() {
+    // A hybrid laptop where the discrete GPU is enumerated first.
+    let nodes = vec![
+        device(0, true, "nouveau", false, GpuKind::Discrete),
+        device(1, true, "i915", true, GpuKind::Integrated),
+        device(2, false, "simpledrm", false, GpuKind::Unknown)
+    ];
+
+    // Displays go to the boot VGA device, offscreen work to any render node.
+    let display = Selector::new(Purpose::Display).select(&nodes).unwrap();
+    assert_eq!(display.path, Path::new("/dev/dri/card1"));
+    let offscreen = Selector::new(Purpose::Offscreen).select(&nodes).unwrap();
+    assert_eq!(offscreen.path, Path::new("/dev/dri/renderD128"));
+
+    // The kind of GPU outranks the boot VGA device.
+    let selection = Selector::new(Purpose::Display).prefer(GpuKind::Discrete).select(&nodes).unwrap();
+    assert_eq!(selection.path, Path::new("/dev/dri/card0"));
+    let selection = Selector::new(Purpose::Offscreen).prefer(GpuKind::Integrated).select(&nodes).unwrap();
+    assert_eq!(selection.path, Path::new("/dev/dri/renderD129"));
+
+    // Devices without a render node fall back to their primary node.
+    let selection = Selector::new(Purpose::Offscreen).driver("simpledrm").select(&nodes).unwrap();
+    assert_eq!(selection.path, Path::new("/dev/dri/card2"));
+}
+
+#[test]
+fn select_override() {
+    let nodes = vec![
+        device(0, true, "amdgpu", true, GpuKind::Discrete),
+        device(1, true, "i915", false, GpuKind::Integrated)
+    ];
+
+    // Paths select the device providing either node.
+    let selector = Selector::new(Purpose::Display).with_override("/dev/dri/renderD129");
+    assert_eq!(selector.select(&nodes).unwrap().path, Path::new("/dev/dri/card1"));
+
+    // Anything else is a driver name, and overrides are strict.
+    let selector = Selector::new(Purpose::Offscreen).with_override("i915");
+    assert_eq!(selector.select(&nodes).unwrap().node.driver.as_ref().unwrap(), "i915");
+    assert!(Selector::new(Purpose::Offscreen).with_override("nouveau").select(&nodes).is_none());
+
+    // Empty values are ignored.
+    let selector = Selector::new(Purpose::Display).with_override(" ");
+    assert_eq!(selector, Selector::new(Purpose::Display));
+
+    std::env::set_var("GBM_TEST_DEVICE", "/dev/dri/card1");
+    let selector = Selector::new(Purpose::Offscreen).with_var("GBM_TEST_DEVICE");
+    assert_eq!(selector.select(&nodes).unwrap().path, Path::new("/dev/dri/renderD129"));
+}
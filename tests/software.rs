@@ -0,0 +1,166 @@
+extern crate gbm;
+
+use gbm::{Allocator, BufferObject, Format};
+use gbm::software::Device;
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::os::unix::io::AsFd;
+use std::rc::Rc;
+
+/// Fills a buffer through any `Allocator` and reads the first pixel back.
+fn fill<A: Allocator>(allocator: &A, value: u8) -> u8 {
+    let mut buffer = allocator.create_buffer((16, 16), Format::XRGB8888, gbm::RENDERING).unwrap();
+    assert_eq!(buffer.size(), (16, 16));
+    allocator.map_buffer(&mut buffer, |data, stride| {
+        assert!(stride >= 16 * 4);
+        for byte in data.iter_mut() {
+            *byte = value;
+        }
+    }).unwrap();
+    allocator.map_buffer(&mut buffer, |data, _| data[0]).unwrap()
+}
+
+#[test]
+fn allocate() {
+    let dev = Device::new();
+    let buffer = dev.buffer((17, 9), Format::ARGB8888, gbm::RENDERING).unwrap();
+    assert_eq!(buffer.size(), (17, 9));
+    assert_eq!(buffer.format(), Format::ARGB8888 as u32);
+    assert!(buffer.stride() >= 17 * 4);
+
+    // Formats with several planes are not supported.
+    assert!(dev.buffer((16, 16), Format::NV12, gbm::RENDERING).is_err());
+
+    assert_eq!(fill(&dev, 0x7f), 0x7f);
+}
+
+#[test]
+fn write() {
+    let dev = Device::new();
+    let mut buffer = dev.buffer((4, 4), Format::XRGB8888, gbm::RENDERING).unwrap();
+    let len = buffer.stride() as usize * 4;
+    assert!(buffer.write(&vec![0; len]).is_err());
+
+    let mut buffer = dev.buffer((4, 4), Format::XRGB8888, gbm::WRITE).unwrap();
+    assert!(buffer.write(&[0; 4]).is_err());
+    buffer.write(&vec![0xaa; len]).unwrap();
+    assert_eq!(buffer.map(gbm::TRANSFER_READ).unwrap()[len - 1], 0xaa);
+}
+
+#[test]
+fn write_packed() {
+    let dev = Device::new();
+    let mut buffer = dev.buffer((3, 2), Format::ARGB8888, gbm::WRITE).unwrap();
+    let stride = buffer.stride() as usize;
+    assert!(buffer.write_packed(&[0; 16]).is_err());
+
+    // Rows land at the start of each row of the buffer, with cleared padding.
+    buffer.map(gbm::TRANSFER_WRITE).unwrap()[stride - 1] = 0x55;
+    buffer.write_packed(&[0xff; 3 * 2 * 4]).unwrap();
+    {
+        let data = buffer.map(gbm::TRANSFER_READ).unwrap();
+        assert!(data[..12].iter().all(|&b| b == 0xff));
+        assert_eq!(data[stride - 1], 0);
+        assert!(data[stride..stride + 12].iter().all(|&b| b == 0xff));
+    }
+
+    // Only formats with 32 bits per pixel can be written this way.
+    let mut buffer = dev.buffer((4, 4), Format::RGB565, gbm::WRITE).unwrap();
+    assert!(buffer.write_packed(&[0; 4 * 4 * 4]).is_err());
+    assert!(buffer.write_packed(&[0; 4 * 4 * 2]).is_err());
+}
+
+#[test]
+fn export_import() {
+    let dev = Device::new();
+    let mut buffer = dev.buffer((8, 8), Format::XRGB8888, gbm::RENDERING).unwrap();
+    buffer.map(gbm::TRANSFER_WRITE).unwrap()[0] = 42;
+
+    // The imported buffer should share memory with the exported one.
+    let fd = buffer.fd().unwrap();
+    let mut descriptor = buffer.descriptor();
+    let mut imported = dev.import_dmabuf(&descriptor, &[fd.as_fd()]).unwrap();
+    assert_eq!(imported.map(gbm::TRANSFER_READ).unwrap()[0], 42);
+    assert_eq!(imported.descriptor(), descriptor);
+
+    // Importing more memory than the file holds fails.
+    descriptor.height = 64;
+    assert!(dev.import_dmabuf(&descriptor, &[fd.as_fd()]).is_err());
+
+    // The file offset is shared with the exporter, so it must not move.
+    let mut file = File::from(fd);
+    file.seek(SeekFrom::Start(16)).unwrap();
+    descriptor.height = 8;
+    dev.import_dmabuf(&descriptor, &[file.as_fd()]).unwrap();
+    assert_eq!(file.stream_position().unwrap(), 16);
+}
+
+#[test]
+fn oversized() {
+    let dev = Device::new();
+    assert!(dev.buffer((u32::MAX, 1), Format::XRGB8888, gbm::RENDERING).is_err());
+
+    // Rows this long would overflow the stride once aligned.
+    assert!(dev.buffer((u32::MAX / 4, 1), Format::XRGB8888, gbm::RENDERING).is_err());
+
+    let buffer = dev.buffer((8, 8), Format::XRGB8888, gbm::RENDERING).unwrap();
+    let fd = buffer.fd().unwrap();
+    let mut descriptor = buffer.descriptor();
+    descriptor.planes[0].stride = u32::MAX;
+    descriptor.height = u32::MAX;
+    assert!(dev.import_dmabuf(&descriptor, &[fd.as_fd()]).is_err());
+}
+
+#[test]
+fn user_data() {
+    let dev = Device::new();
+    let buffer = dev.buffer((16, 16), Format::XRGB8888, gbm::SCANOUT | gbm::RENDERING).unwrap();
+
+    let get = unsafe { buffer.get_user_data::<u32>() };
+    assert_eq!(get, None);
+
+    buffer.set_user_data(Some(Rc::new(12345u32)));
+
+    let weak = {
+        let get = unsafe { buffer.get_user_data::<u32>().unwrap() };
+        assert_eq!(*get, 12345);
+
+        // The wrong type is not returned.
+        assert!(unsafe { buffer.get_user_data::<u64>() }.is_none());
+
+        buffer.set_user_data::<()>(None);
+        Rc::downgrade(&get)
+    };
+
+    assert_eq!(weak.upgrade(), None);
+    assert_eq!(unsafe { buffer.get_user_data::<()>() }, None);
+}
+
+#[test]
+fn surface_locking() {
+    let dev = Device::new();
+    let surface = dev.surface((16, 16), Format::XRGB8888, gbm::SCANOUT | gbm::RENDERING).unwrap();
+
+    // Nothing has been rendered yet.
+    assert!(surface.lock_front_buffer().is_err());
+
+    surface.render(|data, _| data[0] = 1).unwrap();
+    let first = surface.lock_front_buffer().unwrap();
+    assert_eq!(first.size(), (16, 16));
+    first.set_user_data(Some(Rc::new(1u32)));
+
+    // Locked buffers are never handed out for rendering again.
+    let mut locked = vec![first];
+    while surface.has_free_buffers() {
+        surface.render(|data, _| assert_eq!(data[0], 0)).unwrap();
+        locked.push(surface.lock_front_buffer().unwrap());
+    }
+    assert_eq!(locked.len(), gbm::software::SURFACE_BUFFERS);
+    assert!(surface.render(|_, _| ()).is_err());
+
+    // Releasing the first buffer makes it available again, user data intact.
+    locked.remove(0);
+    surface.render(|data, _| assert_eq!(data[0], 1)).unwrap();
+    let again = surface.lock_front_buffer().unwrap();
+    assert_eq!(unsafe { again.get_user_data::<u32>() }.map(|d| *d), Some(1));
+}
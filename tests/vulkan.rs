@@ -0,0 +1,26 @@
+#![cfg(feature = "vulkan")]
+
+extern crate ash;
+extern crate gbm;
+
+use ash::vk;
+use gbm::Format;
+use gbm::vulkan::{from_vk_format, vk_format};
+
+#[test]
+fn format_mapping() {
+    assert_eq!(vk_format(Format::XRGB8888), Some(vk::Format::B8G8R8A8_UNORM));
+    assert_eq!(vk_format(Format::NV12), Some(vk::Format::G8_B8R8_2PLANE_420_UNORM));
+    assert_eq!(vk_format(Format::C8), None);
+    assert_eq!(from_vk_format(vk::Format::B8G8R8A8_SRGB), Some(Format::ARGB8888));
+    assert_eq!(from_vk_format(vk::Format::D32_SFLOAT), None);
+
+    // Every mapped format maps back to itself or its alpha variant.
+    for &format in Format::all() {
+        if let Some(vk_format) = vk_format(format) {
+            let back = from_vk_format(vk_format).unwrap();
+            assert_eq!(vk_format, gbm::vulkan::vk_format(back).unwrap());
+            assert!(back == format || back.has_alpha() && !format.has_alpha());
+        }
+    }
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "x11")]
+
+extern crate gbm;
+extern crate x11rb;
+
+use gbm::{DmabufDescriptor, Format, Modifier, Plane, RENDERING};
+use gbm::x11::{depth_and_bpp, format_for_depth, pixmap_request, reply_descriptor};
+use x11rb::protocol::dri3::{BuffersFromPixmapReply, PIXMAP_FROM_BUFFERS_REQUEST};
+
+use std::fs::File;
+use std::os::unix::io::OwnedFd;
+
+fn fd() -> OwnedFd {
+    File::open("/dev/null").unwrap().into()
+}
+
+#[test]
+fn depth_mapping() {
+    assert_eq!(depth_and_bpp(Format::XRGB8888), Some((24, 32)));
+    assert_eq!(depth_and_bpp(Format::NV12), None);
+    assert_eq!(format_for_depth(32, 32), Some(Format::ARGB8888));
+    assert_eq!(format_for_depth(24, 24), None);
+
+    for &format in Format::all() {
+        if let Some((depth, bpp)) = depth_and_bpp(format) {
+            assert_eq!(format_for_depth(depth, bpp), Some(format));
+        }
+    }
+}
+
+#[test]
+fn pixmap_request_encoding() {
+    let modifier = Modifier(0x0100000000000002);
+    let mut descriptor = DmabufDescriptor {
+        width: 64,
+        height: 16,
+        format: Format::XRGB8888,
+        modifier: modifier,
+        planes: vec![Plane { offset: 0, stride: 256 }, Plane { offset: 4096, stride: 128 }],
+        usage: RENDERING
+    };
+    let request = pixmap_request(7, 9, &descriptor, vec![fd(), fd()]).unwrap();
+    assert_eq!((request.width, request.height, request.depth, request.bpp), (64, 16, 24, 32));
+    assert_eq!((request.stride0, request.offset0), (256, 0));
+    assert_eq!((request.stride1, request.offset1), (128, 4096));
+    assert_eq!((request.stride2, request.offset2, request.stride3, request.offset3), (0, 0, 0, 0));
+
+    let ([bytes], fds) = request.serialize(140);
+    assert_eq!(fds.len(), 2);
+    assert_eq!(bytes.len(), 64);
+    assert_eq!(&bytes[..2], &[140, PIXMAP_FROM_BUFFERS_REQUEST]);
+    assert_eq!(u16::from_ne_bytes([bytes[2], bytes[3]]), 16);
+    assert_eq!(bytes[12], 2);
+    assert_eq!(&bytes[56..64], &modifier.0.to_ne_bytes());
+
+    assert!(pixmap_request(7, 9, &descriptor, vec![fd()]).is_err());
+    descriptor.width = 1 << 16;
+    assert!(pixmap_request(7, 9, &descriptor, vec![fd(), fd()]).is_err());
+    descriptor.width = 64;
+    descriptor.format = Format::NV12;
+    assert!(pixmap_request(7, 9, &descriptor, vec![fd(), fd()]).is_err());
+}
+
+#[test]
+fn reply_mapping() {
+    let mut reply = BuffersFromPixmapReply {
+        sequence: 1,
+        length: 0,
+        width: 64,
+        height: 16,
+        modifier: Modifier::LINEAR.0,
+        depth: 32,
+        bpp: 32,
+        strides: vec![256, 128],
+        offsets: vec![0, 4096],
+        buffers: vec![fd(), fd()]
+    };
+    let descriptor = reply_descriptor(&reply, RENDERING).unwrap();
+    assert_eq!(descriptor, DmabufDescriptor {
+        width: 64,
+        height: 16,
+        format: Format::ARGB8888,
+        modifier: Modifier::LINEAR,
+        planes: vec![Plane { offset: 0, stride: 256 }, Plane { offset: 4096, stride: 128 }],
+        usage: RENDERING
+    });
+
+    reply.buffers.pop();
+    assert!(reply_descriptor(&reply, RENDERING).is_err());
+    reply.depth = 12;
+    assert!(reply_descriptor(&reply, RENDERING).is_err());
+}